@@ -1,53 +1,460 @@
-use crate::todo::Todos;
-use crate::ui::{TodoUI, UiEvent};
-use std::error::Error;
+use crate::config::{expand_home, Config};
+use crate::error::TodoError;
+use crate::formats;
+use crate::history::History;
+use crate::lists::Lists;
+use crate::lock::FileLock;
+use crate::storage::{self, Backend};
+use crate::todo::{new_id, Reload, Todos};
+use crate::ui::{Message, TodoTab, TodoUI, UiEvent};
+use crate::watcher::FileWatcher;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
 use crate::ui::Display;
 
+const UNLOCK_ATTEMPTS: usize = 3;
+
 pub struct App {
+    config: Config,
+    lists: Lists,
     todos: Todos,
     ui: TodoUI,
+    watcher: Option<FileWatcher>,
+    // Held while the list is open; `None` when it was opened read-only
+    instance_lock: Option<FileLock>,
 }
 
 impl App {
-    pub fn new() -> Self {
-        let file_name = "todo.txt";
-        let mut todos = Todos::new(file_name);
-        todos.load_from_file(file_name);
+    pub fn new(config: Config, location: &Path) -> Result<Self, TodoError> {
+        let (lists, path) = Lists::locate(location)?;
+        // Replaced by `open_list` right away
+        let placeholder = Todos::new(storage::open(Path::new(""), Backend::Text, 0));
+        let mut app = Self {
+            todos: placeholder,
+            config,
+            lists,
+            ui: TodoUI::new(),
+            watcher: None,
+            instance_lock: None,
+        };
+        // The screen is needed right away to ask for a passphrase
+        app.ui.initialize()?;
+        // Refuse to start on a list we can't read, saving would overwrite it
+        if let Err(e) = app.open_list(&path) {
+            let _ = app.ui.cleanup();
+            return Err(e);
+        }
+        Ok(app)
+    }
 
-        let ui = TodoUI::new();
+    fn open_list(&mut self, path: &Path) -> Result<(), TodoError> {
+        self.todos.flush()?;
+        let backend = Backend::detect(path, self.config.backend);
+        let mut todos = Todos::new(storage::open(path, backend, self.config.backups));
+        todos.compact_after = self.config.compact_after;
+        todos.backups = self.config.backups;
+        todos.complete_parents = self.config.complete_parents;
+        let mut history_error = None;
+        if self.config.git {
+            match History::open(self.lists.dir()) {
+                Ok(history) => todos.set_history(Some(history)),
+                Err(e) => history_error = Some(e),
+            }
+        }
+        // Reopening the current list, release it first
+        let reopening = path == self.todos.path();
+        if reopening {
+            self.instance_lock = None;
+            self.todos.set_read_only(true);
+        }
+        let (instance_lock, malformed) = match self.load_list(&mut todos, path) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                // Keep the list that is still shown as it was, a cancelled
+                // passphrase mustn't leave it unlocked
+                if reopening {
+                    self.instance_lock = FileLock::instance(path).ok().flatten();
+                    self.todos.set_read_only(self.instance_lock.is_none());
+                }
+                return Err(e);
+            },
+        };
+        self.todos = todos;
+        self.instance_lock = instance_lock;
+        self.ui.set_list_name(self.lists.name_of(path));
+        self.ui.refresh_screen(&self.todos);
+        if self.config.trash_days > 0 && !self.todos.is_read_only() {
+            match self.todos.empty_trash(self.config.trash_days) {
+                Ok(0) => {},
+                Ok(purged) => self.ui.set_message(Message::Info(format!("Purged {} task(s) deleted over {} days ago", purged, self.config.trash_days))),
+                Err(e) => self.ui.set_message(Message::Error(format!("Can't empty the trash: {}", e))),
+            }
+        }
+        if let Some(e) = history_error {
+            self.ui.set_message(Message::Error(format!("History disabled, {}", e)));
+        }
+        if !malformed.is_empty() {
+            let details = malformed.iter().map(|m| m.to_string()).collect::<Vec<_>>().join("; ");
+            self.ui.set_message(Message::Error(format!("{} malformed line(s) in {}: {}", malformed.len(), path.display(), details)));
+        }
 
-        Self { todos, ui }
+        self.watcher = match FileWatcher::new(path) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                self.ui.set_message(Message::Error(format!("Live reload disabled, {}", e)));
+                None
+            }
+        };
+        Ok(())
     }
 
-    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
-        self.ui.initialize()?;
-        loop {
+    // Takes the instance lock of the list, unlocks it if it is encrypted and
+    // reads it, with the lines that were malformed
+    fn load_list(&mut self, todos: &mut Todos, path: &Path) -> Result<(Option<FileLock>, Vec<TodoError>), TodoError> {
+        let instance_lock = FileLock::instance(path)?;
+        todos.set_read_only(instance_lock.is_none());
+        if todos.is_encrypted() {
+            self.unlock(todos, path)?;
+        }
+        let malformed = todos.load_from_file()?;
+        Ok((instance_lock, malformed))
+    }
+
+    // Asks for the passphrase of an encrypted list, an empty one gives up
+    fn unlock(&mut self, todos: &mut Todos, path: &Path) -> Result<(), TodoError> {
+        for _ in 0..UNLOCK_ATTEMPTS {
             self.ui.render(&self.todos)?;
-            if let Some(event) = self.ui.read_event(&mut self.todos)? {
-                self.handle_event(event)?;
+            let passphrase = self.ui.read_secret(&format!("Passphrase for {}:", self.lists.name_of(path)))?;
+            if passphrase.is_empty() {
+                break;
+            }
+            match todos.unlock(&passphrase) {
+                Err(TodoError::Decrypt(_)) => self.ui.set_message(Message::Error(String::from("Wrong passphrase"))),
+                result => return result,
             }
         }
+        Err(TodoError::Locked(path.to_path_buf()))
     }
 
-    fn handle_event(&mut self, event: UiEvent) -> Result<(), Box<dyn Error>> {
-        match event {
-            UiEvent::Quit => {
-                self.ui.cleanup()?;
-                std::process::exit(0);
+    // Closes the open list so its files can be renamed or deleted
+    fn close_list(&mut self) {
+        let _ = self.todos.flush();
+        self.watcher = None;
+        self.instance_lock = None;
+    }
+
+    pub fn run(&mut self) -> Result<(), TodoError> {
+        let result = self.event_loop();
+        let flushed = self.todos.flush();
+        self.ui.cleanup()?;
+        result.and(flushed)
+    }
+
+    fn event_loop(&mut self) -> Result<(), TodoError> {
+        // Only redraw when something changed, clearing the screen on every
+        // poll makes it flicker
+        let mut redraw = true;
+        loop {
+            if redraw {
+                self.ui.render(&self.todos)?;
+                redraw = false;
+            }
+            if let Some(event) = self.ui.read_event(&self.todos)? {
+                redraw = true;
+                if let UiEvent::Quit = event {
+                    return Ok(());
+                }
+                // Errors from a single action are shown and the app keeps going
+                if let Err(e) = self.handle_event(event) {
+                    self.ui.set_message(Message::Error(e.to_string()));
+                }
+                self.ui.refresh_screen(&self.todos);
+            }
+            if self.watcher.as_ref().is_some_and(|watcher| watcher.changed()) {
+                self.reload();
+                redraw = true;
+            }
+        }
+    }
+
+    fn reload(&mut self) {
+        match self.todos.reload() {
+            Ok(Reload::Unchanged) => {},
+            Ok(Reload::Reloaded) => {
+                self.ui.set_message(Message::Info(format!("Reloaded {}", self.todos.path().display())));
             },
-            UiEvent::AddTodo => {
-                let input = self.ui.read_line()?;
-                self.todos.add_todo(&input);
-                self.ui.add_todo();
+            Ok(Reload::Merged(conflicts)) if conflicts.is_empty() => {
+                self.ui.set_message(Message::Info(format!("Merged changes from {}", self.todos.path().display())));
+            },
+            Ok(Reload::Merged(conflicts)) => {
+                self.ui.set_message(Message::Error(format!("{} conflict(s) merging {}: {}", conflicts.len(), self.todos.path().display(), conflicts.join("; "))));
+            },
+            Err(e) => {
+                self.ui.set_message(Message::Error(format!("Can't reload {}: {}", self.todos.path().display(), e)));
+            },
+        }
+        self.ui.refresh_screen(&self.todos);
+    }
 
+    fn handle_event(&mut self, event: UiEvent) -> Result<(), TodoError> {
+        match event {
+            UiEvent::Quit => {},
+            UiEvent::AddTodo => {
+                let input = self.ui.read_line();
+                self.ui.close_prompt();
+                self.todos.add_todo(&input?)?;
+            },
+            UiEvent::AddSubtask(parent) => {
+                let input = self.ui.read_line();
+                self.ui.close_prompt();
+                let input = input?;
+                if !input.trim().is_empty() {
+                    self.todos.add_subtask(&parent, &input)?;
+                    self.ui.expand(&parent);
+                }
+            },
+            UiEvent::EditNotes(id) => {
+                let notes = self.todos.get_task(&id).map(|todo| todo.notes.clone()).unwrap_or_default();
+                if let Some(notes) = self.ui.read_text("Notes (ctrl-d: save, esc: cancel):", &notes)? {
+                    self.todos.set_notes(&id, &notes)?;
+                }
+            },
+            UiEvent::EditNotesInEditor(id) => self.edit_notes_in_editor(&id)?,
+            UiEvent::EditTodo(id) => {
+                let input = self.ui.read_line();
+                self.ui.close_prompt();
+                let input = input?;
+                if !input.trim().is_empty() {
+                    self.todos.edit_todo(&id, input.trim())?;
+                }
+            },
+            UiEvent::RaisePriority(id) => {
+                self.todos.raise_priority(&id)?;
+            },
+            UiEvent::LowerPriority(id) => {
+                self.todos.lower_priority(&id)?;
+            },
+            UiEvent::RemoveTodo(id) => {
+                self.todos.remove_todo(&id)?;
+                self.ui.set_message(Message::Info(String::from("Moved to the trash, restore it from the Trash tab")));
+            },
+            UiEvent::RestoreDeleted(id) => {
+                self.todos.restore_deleted(&id)?;
+            },
+            UiEvent::PurgeTodo(id) => {
+                let input = self.ui.read_line();
+                self.ui.close_prompt();
+                if input?.trim() == "y" {
+                    self.todos.purge(&id)?;
+                }
+            },
+            UiEvent::ToggleTodo(id) => {
+                self.todos.toggle_todo(&id)?;
             },
             UiEvent::ChangeTab(screen) => {
+                match screen {
+                    TodoTab::ArchiveList => self.todos.load_archive()?,
+                    TodoTab::TrashList => self.todos.load_trash()?,
+                    _ => {},
+                }
                 self.ui.change_screen(screen);
             },
-            UiEvent::RefreshUI => {
-                self.ui.refresh_screen(&self.todos);
+            UiEvent::Archive => {
+                let archived = self.todos.archive()?;
+                self.ui.set_message(Message::Info(format!("Archived {} done task(s)", archived)));
+            },
+            UiEvent::Unarchive(id) => {
+                self.todos.unarchive(&id)?;
+            },
+            UiEvent::Import => {
+                let input = self.ui.read_line();
+                self.ui.close_prompt();
+                let input = input?;
+                if !input.trim().is_empty() {
+                    let file = expand_home(input.trim());
+                    let (format, parsed) = formats::import(&file)?;
+                    let preview = self.todos.preview_import(&parsed.todos);
+                    self.ui.open_prompt(Some(&format!("{} ({}): {}, {} skipped. Import? (y/n)", file.display(), format, preview, parsed.skipped)))?;
+                    let input = self.ui.read_line();
+                    self.ui.close_prompt();
+                    if input?.trim() == "y" {
+                        let counts = self.todos.import(parsed.todos, &file.display().to_string())?;
+                        self.ui.set_message(Message::Info(format!("Imported {}: {}", file.display(), counts)));
+                    }
+                }
+            },
+            UiEvent::Export => {
+                let input = self.ui.read_line();
+                self.ui.close_prompt();
+                let input = input?;
+                if !input.trim().is_empty() {
+                    let file = expand_home(input.trim());
+                    let tasks = self.ui.visible_tasks(&self.todos);
+                    formats::export(&file, &tasks, &self.ui.view_title())?;
+                    self.ui.set_message(Message::Info(format!("Exported {} task(s) to {}", tasks.len(), file.display())));
+                }
+            },
+            UiEvent::Search => {
+                let input = self.ui.read_line();
+                self.ui.close_prompt();
+                self.ui.set_search(&input?);
+            },
+            UiEvent::RestoreBackup => {
+                self.todos.restore_backup(1)?;
+                self.ui.set_message(Message::Info(format!("Restored {}.bak.1 (press R again to undo)", self.todos.path().display())));
+            },
+            UiEvent::OpenListPicker => {
+                let names = self.lists.all()?.into_iter().map(|(name, _)| name).collect();
+                self.ui.open_list_picker(names);
+            },
+            UiEvent::SwitchList(name) => {
+                let path = self.lists.find(&name)?;
+                self.open_list(&path)?;
             },
+            UiEvent::CreateList => {
+                let input = self.ui.read_line();
+                self.ui.close_prompt();
+                let input = input?;
+                if !input.trim().is_empty() {
+                    let path = self.lists.create(&input)?;
+                    self.open_list(&path)?;
+                }
+            },
+            UiEvent::RenameList(name) => {
+                let input = self.ui.read_line();
+                self.ui.close_prompt();
+                let input = input?;
+                if !input.trim().is_empty() {
+                    let path = self.lists.find(&name)?;
+                    let active = path == self.todos.path();
+                    if active {
+                        self.close_list();
+                    }
+                    let renamed = self.lists.rename(&path, &input);
+                    if active {
+                        // Reopen whichever name the list ended up with
+                        let path = renamed.as_ref().map_or(path.clone(), |p| p.clone());
+                        self.open_list(&path)?;
+                    }
+                    renamed?;
+                }
+            },
+            UiEvent::DeleteList(name) => {
+                let input = self.ui.read_line();
+                self.ui.close_prompt();
+                if input?.trim() == "y" {
+                    let path = self.lists.find(&name)?;
+                    let active = path == self.todos.path();
+                    if active {
+                        self.close_list();
+                    }
+                    let deleted = self.lists.delete(&path);
+                    if active {
+                        let path = match deleted {
+                            Ok(()) => self.lists.default_list()?,
+                            Err(_) => path,
+                        };
+                        self.open_list(&path)?;
+                    }
+                    deleted?;
+                    self.ui.set_message(Message::Info(format!("Deleted list \"{}\"", name)));
+                }
+            },
+            UiEvent::OpenHistory => {
+                if !self.config.git {
+                    self.ui.set_message(Message::Info(String::from("History is off, set `git = true` in the config or TODO_GIT=1")));
+                    return Ok(());
+                }
+                let versions = self.todos.versions()?
+                    .into_iter()
+                    .map(|version| (version.id.to_string(), format!("{}  {}", version.time.format("%Y-%m-%d %H:%M"), version.message)))
+                    .collect();
+                self.ui.open_history(versions);
+            },
+            UiEvent::RestoreVersion(id) => {
+                let id = git2::Oid::from_str(&id)?;
+                self.todos.restore_version(id)?;
+                self.ui.set_message(Message::Info(format!("Restored version {:.7}, the replaced state stays in the history", id)));
+            },
+            UiEvent::ChangeEncryption => self.change_encryption()?,
+            UiEvent::RefreshUI => {},
+        }
+        Ok(())
+    }
+
+    // Hands the notes of a task to $VISUAL or $EDITOR in a temporary file.
+    // Encrypted lists don't allow it, the file would be plain text.
+    fn edit_notes_in_editor(&mut self, id: &str) -> Result<(), TodoError> {
+        if self.todos.is_encrypted() {
+            return Err(TodoError::Unsupported(String::from("the notes of an encrypted list can't go through a plain file, edit them with n")));
         }
+        let notes = self.todos.get_task(id).map(|todo| todo.notes.clone()).unwrap_or_default();
+        let editor = ["VISUAL", "EDITOR"]
+            .iter()
+            .find_map(|name| env::var(name).ok().filter(|value| !value.trim().is_empty()))
+            .unwrap_or_else(|| String::from("vi"));
+        let path = env::temp_dir().join(format!("todo-rs-notes-{}.txt", new_id()));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let contents = match notes.is_empty() {
+            true => String::new(),
+            false => format!("{}\n", notes),
+        };
+        options.open(&path)?.write_all(contents.as_bytes())?;
+
+        // The editor gets the terminal to itself
+        self.ui.cleanup()?;
+        let mut words = editor.split_whitespace();
+        let status = Command::new(words.next().unwrap_or("vi")).args(words).arg(&path).status();
+        self.ui.initialize()?;
+        let edited = match status {
+            Ok(status) if status.success() => fs::read_to_string(&path).map_err(TodoError::from),
+            Ok(status) => Err(TodoError::Unsupported(format!("{} exited with {}, notes left unchanged", editor, status))),
+            Err(e) => Err(TodoError::Unsupported(format!("can't run {}: {}", editor, e))),
+        };
+        let _ = fs::remove_file(&path);
+        self.todos.set_notes(id, &edited?)
+    }
+
+    // Encrypts the open list, changes its passphrase, or decrypts it when
+    // the new passphrase is left empty
+    fn change_encryption(&mut self) -> Result<(), TodoError> {
+        let name = self.lists.name_of(self.todos.path());
+        let encrypted = self.todos.is_encrypted();
+        let label = match encrypted {
+            true => "New passphrase (empty to decrypt):",
+            false => "Passphrase:",
+        };
+        let passphrase = self.ui.read_secret(label)?;
+        if passphrase.is_empty() {
+            if encrypted {
+                self.ui.open_prompt(Some(&format!("Store \"{}\" unencrypted? (y/n)", name)))?;
+                let input = self.ui.read_line();
+                self.ui.close_prompt();
+                if input?.trim() == "y" {
+                    self.todos.set_encryption(None, self.plain_backend(), self.config.backups)?;
+                    self.ui.set_message(Message::Info(format!("Decrypted \"{}\"", name)));
+                }
+            }
+            return Ok(());
+        }
+        if *self.ui.read_secret("Repeat passphrase:")? != *passphrase {
+            self.ui.set_message(Message::Error(String::from("Passphrases don't match, nothing changed")));
+            return Ok(());
+        }
+        self.todos.set_encryption(Some(&passphrase), self.plain_backend(), self.config.backups)?;
+        let done = if encrypted { "Changed the passphrase of" } else { "Encrypted" };
+        self.ui.set_message(Message::Info(format!("{} \"{}\", backups and journal removed", done, name)));
         Ok(())
     }
+
+    // The backend the open list uses when it isn't encrypted
+    fn plain_backend(&self) -> Backend {
+        self.config.backend.unwrap_or_else(|| Backend::from_path(self.todos.path()))
+    }
 }
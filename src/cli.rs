@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: todo-rs [OPTIONS] [COMMAND]
+
+Commands:
+  archive            Move done tasks to the archive (done.txt for todo.txt)
+  encrypt            Encrypt the list with a passphrase
+  decrypt            Store the list unencrypted again
+  rekey              Change the passphrase of an encrypted list
+  restore [N]        Roll the list back to its N-th backup, 1 (default) is
+                     the latest; the list it replaces becomes backup 1
+  import <FILE>      Add the tasks of a Markdown checklist (.md), an
+                     iCalendar file (.ics), a Taskwarrior export (.json)
+                     or a Todoist backup (.csv, .json)
+  export <FILE>      Write the tasks to a Markdown checklist (.md), an
+                     iCalendar file (.ics), an HTML report (.html) or a
+                     spreadsheet (.csv)
+
+Without a command the list is opened in the TUI.
+
+Options:
+  -f, --file <PATH>  List file or directory of lists to open
+  --tab <TAB>        Tasks to export: all (default), done or undone
+  --search <TEXT>    Only export tasks containing TEXT
+  --dry-run          Only show what import would change
+  -y, --yes          Import without asking for confirmation
+  -h, --help         Print this help
+
+Without --file the location comes from TODO_FILE, then `file = ...` in
+$XDG_CONFIG_HOME/todo-rs/config, then $XDG_DATA_HOME/todo-rs/.";
+
+pub enum Command {
+    Archive,
+    Encrypt,
+    Decrypt,
+    Rekey,
+    // The number of the `.bak.N` backup, from 1
+    Restore(usize),
+    // Shows what would change and asks before saving, unless `yes`
+    Import { path: PathBuf, dry_run: bool, yes: bool },
+    // Only the done or undone tasks when `done` is set, as with `--tab`,
+    // and the ones matching `search`
+    Export { path: PathBuf, done: Option<bool>, search: Option<String> },
+}
+
+pub struct Cli {
+    pub file: Option<PathBuf>,
+    pub help: bool,
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut cli = Cli { file: None, help: false, command: None };
+        let mut args = args.into_iter().peekable();
+        let (mut tab, mut search) = (None, None);
+        let (mut dry_run, mut yes) = (false, false);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => cli.help = true,
+                "-f" | "--file" => {
+                    let value = args.next().ok_or_else(|| format!("{} needs a path", arg))?;
+                    cli.file = Some(PathBuf::from(value));
+                },
+                "archive" if cli.command.is_none() => cli.command = Some(Command::Archive),
+                "encrypt" if cli.command.is_none() => cli.command = Some(Command::Encrypt),
+                "decrypt" if cli.command.is_none() => cli.command = Some(Command::Decrypt),
+                "rekey" if cli.command.is_none() => cli.command = Some(Command::Rekey),
+                "restore" if cli.command.is_none() => {
+                    let n = match args.next_if(|value| value.chars().all(|c| c.is_ascii_digit())) {
+                        Some(value) => value.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("no backup \"{}\", they are numbered from 1", value))?,
+                        None => 1,
+                    };
+                    cli.command = Some(Command::Restore(n));
+                },
+                "import" | "export" if cli.command.is_none() => {
+                    let path = PathBuf::from(args.next().ok_or_else(|| format!("{} needs a file", arg))?);
+                    cli.command = Some(match arg.as_str() {
+                        "import" => Command::Import { path, dry_run: false, yes: false },
+                        _ => Command::Export { path, done: None, search: None },
+                    });
+                },
+                "--dry-run" => dry_run = true,
+                "-y" | "--yes" => yes = true,
+                "--search" => search = Some(args.next().ok_or_else(|| format!("{} needs a text", arg))?),
+                "--tab" => tab = Some(args.next().ok_or_else(|| format!("{} needs a tab", arg))?),
+                _ => match arg.strip_prefix("--file=") {
+                    Some(value) => cli.file = Some(PathBuf::from(value)),
+                    None => return Err(format!("unexpected argument \"{}\"", arg)),
+                },
+            }
+        }
+        if dry_run || yes {
+            let Some(Command::Import { dry_run: d, yes: y, .. }) = cli.command.as_mut() else {
+                return Err(String::from("--dry-run and --yes only apply to import"));
+            };
+            (*d, *y) = (dry_run, yes);
+        }
+        if tab.is_some() || search.is_some() {
+            let Some(Command::Export { done, search: query, .. }) = cli.command.as_mut() else {
+                return Err(String::from("--tab and --search only apply to export"));
+            };
+            *query = search;
+            *done = match tab.as_deref() {
+                None | Some("all") => None,
+                Some("done") => Some(true),
+                Some("undone") => Some(false),
+                Some(tab) => return Err(format!("unknown tab \"{}\", expected all, done or undone", tab)),
+            };
+        }
+        Ok(cli)
+    }
+}
@@ -0,0 +1,110 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use zeroize::Zeroizing;
+
+use crate::cli::Command;
+use crate::config::Config;
+use crate::error::TodoError;
+use crate::formats;
+use crate::history::History;
+use crate::lists::Lists;
+use crate::lock::FileLock;
+use crate::storage::{self, Backend};
+use crate::todo::Todos;
+
+// Runs a subcommand on the list at `location` without starting the TUI
+pub fn run(command: Command, config: &Config, location: &Path) -> Result<(), TodoError> {
+    let (lists, path) = Lists::locate(location)?;
+    let name = lists.name_of(&path);
+    let _instance_lock = FileLock::instance(&path)?.ok_or_else(|| TodoError::ReadOnly(path.clone()))?;
+    let mut todos = Todos::new(storage::open(&path, Backend::detect(&path, config.backend), config.backups));
+    todos.compact_after = config.compact_after;
+    todos.backups = config.backups;
+    if config.git {
+        todos.set_history(Some(History::open(lists.dir())?));
+    }
+    if todos.is_encrypted() {
+        todos.unlock(&prompt(&format!("Passphrase for {}: ", name))?)?;
+    }
+    for malformed in todos.load_from_file()? {
+        eprintln!("{}: {}", path.display(), malformed);
+    }
+    if config.trash_days > 0 {
+        todos.empty_trash(config.trash_days)?;
+    }
+    let plain_backend = config.backend.unwrap_or_else(|| Backend::from_path(&path));
+
+    match command {
+        Command::Encrypt | Command::Rekey => {
+            match (&command, todos.is_encrypted()) {
+                (Command::Encrypt, true) => return Err(TodoError::Unsupported(format!("{} is already encrypted", name))),
+                (Command::Rekey, false) => return Err(TodoError::Unsupported(format!("{} isn't encrypted", name))),
+                _ => {},
+            }
+            let passphrase = prompt("New passphrase: ")?;
+            if passphrase.is_empty() {
+                return Err(TodoError::Unsupported(String::from("the passphrase can't be empty")));
+            }
+            if *prompt("Repeat passphrase: ")? != *passphrase {
+                return Err(TodoError::Unsupported(String::from("passphrases don't match, nothing changed")));
+            }
+            todos.set_encryption(Some(&passphrase), plain_backend, config.backups)?;
+            println!("{}: done, backups and journal removed", path.display());
+        },
+        Command::Restore(n) => {
+            for malformed in todos.restore_backup(n)? {
+                eprintln!("{}.bak.{}: {}", path.display(), n, malformed);
+            }
+            println!("Restored {}.bak.{}, the list it replaced is now {0}.bak.1", path.display(), n);
+        },
+        Command::Archive => {
+            let archived = todos.archive()?;
+            println!("Archived {} done task(s) from {}", archived, path.display());
+        },
+        Command::Import { path: file, dry_run, yes } => {
+            let (format, parsed) = formats::import(&file)?;
+            let preview = todos.preview_import(&parsed.todos);
+            println!("{} ({}): {}, {} skipped", file.display(), format, preview, parsed.skipped);
+            if dry_run || !(yes || confirm(&format!("Import into {}?", path.display()))?) {
+                return Ok(());
+            }
+            let counts = todos.import(parsed.todos, &file.display().to_string())?;
+            println!("Imported {} into {}: {}", file.display(), path.display(), counts);
+        },
+        Command::Export { path: file, done, search } => {
+            let mut tasks = todos.get_tasks(done);
+            let mut title = format!("{}: {}", name, match done {
+                None => "All",
+                Some(true) => "Done",
+                Some(false) => "Undone",
+            });
+            if let Some(search) = &search {
+                tasks.retain(|todo| todo.matches(search));
+                title.push_str(&format!(" matching \"{}\"", search));
+            }
+            formats::export(&file, &tasks, &title)?;
+            println!("Exported {} task(s) to {}", tasks.len(), file.display());
+        },
+        Command::Decrypt => {
+            if !todos.is_encrypted() {
+                return Err(TodoError::Unsupported(format!("{} isn't encrypted", name)));
+            }
+            todos.set_encryption(None, plain_backend, config.backups)?;
+            println!("{}: done, backups and journal removed", path.display());
+        },
+    }
+    Ok(())
+}
+
+fn confirm(question: &str) -> Result<bool, TodoError> {
+    print!("{} (y/n) ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim() == "y")
+}
+
+fn prompt(label: &str) -> Result<Zeroizing<String>, TodoError> {
+    Ok(Zeroizing::new(rpassword::prompt_password(label)?))
+}
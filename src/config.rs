@@ -0,0 +1,139 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::TodoError;
+use crate::journal::DEFAULT_COMPACT_AFTER;
+use crate::storage::{Backend, DEFAULT_BACKUPS};
+
+const APP_DIR: &str = "todo-rs";
+const CONFIG_FILE: &str = "config";
+const DEFAULT_TRASH_DAYS: u32 = 30;
+
+pub struct Config {
+    // List file or directory of lists, see `data_location`
+    pub file: Option<PathBuf>,
+    // Number of rotating `.bak.N` snapshots kept next to the list
+    pub backups: usize,
+    // Storage backend, guessed from the file extension when unset
+    pub backend: Option<Backend>,
+    // Journal entries to collect before rewriting the list
+    pub compact_after: usize,
+    // Commit every save to a git repository in the lists directory
+    pub git: bool,
+    // Deleted tasks are purged after this many days, 0 keeps them
+    pub trash_days: u32,
+    // Complete a task once all of its subtasks are done
+    pub complete_parents: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            file: None,
+            backups: DEFAULT_BACKUPS,
+            backend: None,
+            compact_after: DEFAULT_COMPACT_AFTER,
+            git: false,
+            trash_days: DEFAULT_TRASH_DAYS,
+            complete_parents: false,
+        }
+    }
+}
+
+impl Config {
+    // Defaults, then the config file, then the environment
+    pub fn load() -> Result<Self, TodoError> {
+        let mut config = Config::default();
+        if let Some(path) = config_path() {
+            config.read_file(&path)?;
+        }
+        if let Some(file) = env::var_os("TODO_FILE").filter(|value| !value.is_empty()) {
+            config.file = Some(PathBuf::from(file));
+        }
+        if let Some(backups) = env::var("TODO_BACKUPS").ok().and_then(|value| value.parse().ok()) {
+            config.backups = backups;
+        }
+        if let Some(compact_after) = env::var("TODO_COMPACT_AFTER").ok().and_then(|value| value.parse().ok()) {
+            config.compact_after = compact_after;
+        }
+        if let Some(trash_days) = env::var("TODO_TRASH_DAYS").ok().and_then(|value| value.parse().ok()) {
+            config.trash_days = trash_days;
+        }
+        if let Some(git) = env::var("TODO_GIT").ok().and_then(|value| parse_bool(&value)) {
+            config.git = git;
+        }
+        if let Some(complete_parents) = env::var("TODO_COMPLETE_PARENTS").ok().and_then(|value| parse_bool(&value)) {
+            config.complete_parents = complete_parents;
+        }
+        if let Some(backend) = env::var("TODO_BACKEND").ok().filter(|value| !value.is_empty()) {
+            let backend = Backend::from_name(&backend)
+                .ok_or_else(|| TodoError::Unsupported(format!("unknown backend \"{}\" in TODO_BACKEND", backend)))?;
+            config.backend = Some(backend);
+        }
+        Ok(config)
+    }
+
+    // `key = value` lines, `#` starts a comment
+    fn read_file(&mut self, path: &Path) -> Result<(), TodoError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| TodoError::Parse {
+                line: n + 1,
+                message: format!("{} in {}", message, path.display()),
+            };
+            let (key, value) = line.split_once('=').ok_or_else(|| error(String::from("expected key = value")))?;
+            let value = value.trim();
+            match key.trim() {
+                "file" => self.file = Some(expand_home(value)),
+                "backups" => self.backups = value.parse().map_err(|_| error(format!("invalid backups \"{}\"", value)))?,
+                "compact_after" => self.compact_after = value.parse().map_err(|_| error(format!("invalid compact_after \"{}\"", value)))?,
+                "trash_days" => self.trash_days = value.parse().map_err(|_| error(format!("invalid trash_days \"{}\"", value)))?,
+                "git" => self.git = parse_bool(value).ok_or_else(|| error(format!("invalid git \"{}\", expected true or false", value)))?,
+                "complete_parents" => self.complete_parents = parse_bool(value).ok_or_else(|| error(format!("invalid complete_parents \"{}\", expected true or false", value)))?,
+                "backend" => self.backend = Some(Backend::from_name(value).ok_or_else(|| error(format!("unknown backend \"{}\"", value)))?),
+                key => return Err(error(format!("unknown key \"{}\"", key))),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR).join(CONFIG_FILE))
+}
+
+// Where the lists live: the `--file` flag, then `TODO_FILE` or the config
+// file (already folded into `config.file`), then `$XDG_DATA_HOME/todo-rs/`
+pub fn data_location(flag: Option<PathBuf>, config: &Config) -> Result<PathBuf, TodoError> {
+    if let Some(path) = flag.or_else(|| config.file.clone()) {
+        return Ok(path);
+    }
+    dirs::data_dir()
+        .map(|dir| dir.join(APP_DIR))
+        .ok_or_else(|| TodoError::NotFound(String::from("data directory (set --file or TODO_FILE)")))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn expand_home(value: &str) -> PathBuf {
+    match (value.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(value),
+    }
+}
@@ -0,0 +1,155 @@
+use chrono::{Datelike, Days, Duration, Months, NaiveDate, Weekday};
+
+use crate::error::TodoError;
+use crate::todo::DATE_FORMAT;
+
+// Phrases after `due:` can run over a few words: `due:in 3 days`
+const MAX_PHRASE_WORDS: usize = 3;
+
+// Rewrites the `due:` tag of a typed-in description to a todo.txt date:
+//   Pay rent due:next fri  ->  Pay rent due:2023-05-12
+// A due date that can't be read is an error rather than a tag that is
+// silently ignored.
+pub fn expand_due(description: &str, today: NaiveDate) -> Result<String, TodoError> {
+    let words = description.split_whitespace().collect::<Vec<_>>();
+    let Some(start) = words.iter().position(|word| word.starts_with("due:")) else {
+        return Ok(description.to_string());
+    };
+    let first = &words[start]["due:".len()..];
+    let longest = MAX_PHRASE_WORDS.min(words.len() - start);
+    let found = (1..=longest).rev().find_map(|count| {
+        let phrase = std::iter::once(first).chain(words[start + 1..start + count].iter().copied()).collect::<Vec<_>>().join(" ");
+        parse_due(&phrase, today).map(|date| (count, date))
+    });
+    let Some((count, date)) = found else {
+        return Err(TodoError::Unsupported(format!(
+            "can't read the due date \"{}\", try due:2023-05-12, due:tomorrow, due:fri, due:next fri or due:in 3 days",
+            first,
+        )));
+    };
+    let mut expanded = words[..start].to_vec();
+    let due = format!("due:{}", date.format(DATE_FORMAT));
+    expanded.push(&due);
+    expanded.extend(&words[start + count..]);
+    Ok(expanded.join(" "))
+}
+
+// `2023-05-12`, `today`, `tomorrow`, `fri`, `next fri`, `next week`,
+// `next month`, `in 3 days` (or weeks, months) and the short `3d`, `2w`, `1m`
+fn parse_due(phrase: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let phrase = phrase.trim().to_lowercase();
+    if let Ok(date) = NaiveDate::parse_from_str(&phrase, DATE_FORMAT) {
+        return Some(date);
+    }
+    let words = phrase.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+        ["today" | "tod"] => Some(today),
+        ["tomorrow" | "tmr" | "tom"] => today.succ_opt(),
+        ["yesterday"] => today.pred_opt(),
+        ["next", "week"] => Some(today + Duration::days(days_to_next_week(today))),
+        ["next", "month"] => today.checked_add_months(Months::new(1)),
+        ["next", "year"] => today.checked_add_months(Months::new(12)),
+        // The weekday of next week, where weeks start on Monday
+        ["next", day] => {
+            let monday = today + Duration::days(days_to_next_week(today));
+            Some(monday + Duration::days(weekday(day)?.num_days_from_monday() as i64))
+        },
+        // The next one, a week from today when it's today
+        [day] if weekday(day).is_some() => {
+            let ahead = (weekday(day)?.num_days_from_monday() as i64 - today.weekday().num_days_from_monday() as i64 + 6) % 7 + 1;
+            Some(today + Duration::days(ahead))
+        },
+        ["in", "a", unit] => offset(today, 1, unit),
+        ["in", count, unit] => offset(today, count.parse().ok()?, unit),
+        [short] => {
+            let split = short.find(|c: char| !c.is_ascii_digit()).filter(|&split| split > 0)?;
+            offset(today, short[..split].parse().ok()?, &short[split..])
+        },
+        _ => None,
+    }
+}
+
+// How a due date compares to today, for a narrow column: `today`,
+// `in 3 days`, `2 days ago`, and the date itself when it's weeks away
+pub fn relative(date: NaiveDate, today: NaiveDate) -> String {
+    match (date - today).num_days() {
+        0 => String::from("today"),
+        1 => String::from("tomorrow"),
+        -1 => String::from("yesterday"),
+        days @ 2..=13 => format!("in {} days", days),
+        days @ -13..=-2 => format!("{} days ago", -days),
+        _ => date.format(DATE_FORMAT).to_string(),
+    }
+}
+
+fn days_to_next_week(today: NaiveDate) -> i64 {
+    7 - today.weekday().num_days_from_monday() as i64
+}
+
+fn weekday(name: &str) -> Option<Weekday> {
+    match name {
+        "mon" | "monday" => Some(Weekday::Mon),
+        "tue" | "tues" | "tuesday" => Some(Weekday::Tue),
+        "wed" | "wednesday" => Some(Weekday::Wed),
+        "thu" | "thur" | "thurs" | "thursday" => Some(Weekday::Thu),
+        "fri" | "friday" => Some(Weekday::Fri),
+        "sat" | "saturday" => Some(Weekday::Sat),
+        "sun" | "sunday" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn offset(today: NaiveDate, count: u32, unit: &str) -> Option<NaiveDate> {
+    match unit {
+        "d" | "day" | "days" => today.checked_add_days(Days::new(count as u64)),
+        "w" | "week" | "weeks" => today.checked_add_days(Days::new(count as u64 * 7)),
+        "m" | "month" | "months" => today.checked_add_months(Months::new(count)),
+        "y" | "year" | "years" => today.checked_add_months(Months::new(count.checked_mul(12)?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A Wednesday
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 5, 10).unwrap()
+    }
+
+    fn due(phrase: &str) -> Option<String> {
+        parse_due(phrase, today()).map(|date| date.format(DATE_FORMAT).to_string())
+    }
+
+    #[test]
+    fn phrases() {
+        assert_eq!(due("2023-06-01").as_deref(), Some("2023-06-01"));
+        assert_eq!(due("today").as_deref(), Some("2023-05-10"));
+        assert_eq!(due("Tomorrow").as_deref(), Some("2023-05-11"));
+        assert_eq!(due("fri").as_deref(), Some("2023-05-12"));
+        assert_eq!(due("wednesday").as_deref(), Some("2023-05-17"));
+        assert_eq!(due("next week").as_deref(), Some("2023-05-15"));
+        assert_eq!(due("next fri").as_deref(), Some("2023-05-19"));
+        assert_eq!(due("next month").as_deref(), Some("2023-06-10"));
+        assert_eq!(due("in 3 days").as_deref(), Some("2023-05-13"));
+        assert_eq!(due("in a week").as_deref(), Some("2023-05-17"));
+        assert_eq!(due("2w").as_deref(), Some("2023-05-24"));
+        assert_eq!(due("1m").as_deref(), Some("2023-06-10"));
+    }
+
+    #[test]
+    fn nonsense_is_not_a_date() {
+        for phrase in ["", "someday", "next", "in 3 lightyears", "3", "d", "2023-02-30"] {
+            assert_eq!(due(phrase), None, "{}", phrase);
+        }
+    }
+
+    #[test]
+    fn due_phrases_are_expanded_in_place() {
+        assert_eq!(expand_due("Pay rent due:next fri +home", today()).unwrap(), "Pay rent due:2023-05-19 +home");
+        assert_eq!(expand_due("Pay rent due:in 2 days", today()).unwrap(), "Pay rent due:2023-05-12");
+        assert_eq!(expand_due("No date here", today()).unwrap(), "No date here");
+        assert!(expand_due("Pay rent due:someday", today()).is_err());
+    }
+}
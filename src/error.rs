@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum TodoError {
+    Io(io::Error),
+    // A line of a list file that couldn't be read, numbered from 1
+    Parse { line: usize, message: String },
+    NotFound(String),
+    // Another instance has the list open
+    ReadOnly(PathBuf),
+    Database(rusqlite::Error),
+    Watch(notify::Error),
+    Git(git2::Error),
+    // The list is encrypted and no passphrase was given yet
+    Locked(PathBuf),
+    // Wrong passphrase, or the encrypted file was tampered with
+    Decrypt(PathBuf),
+    Unsupported(String),
+}
+
+impl fmt::Display for TodoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TodoError::Io(e) => write!(f, "{}", e),
+            TodoError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            TodoError::NotFound(what) => write!(f, "{} not found", what),
+            TodoError::ReadOnly(path) => write!(f, "{} is open in another instance, changes can't be saved", path.display()),
+            TodoError::Database(e) => write!(f, "database error: {}", e),
+            TodoError::Watch(e) => write!(f, "can't watch for changes: {}", e),
+            TodoError::Git(e) => write!(f, "git error: {}", e.message()),
+            TodoError::Locked(path) => write!(f, "{} is encrypted, a passphrase is needed to open it", path.display()),
+            TodoError::Decrypt(path) => write!(f, "can't decrypt {}: wrong passphrase or damaged file", path.display()),
+            TodoError::Unsupported(what) => write!(f, "{}", what),
+        }
+    }
+}
+
+impl Error for TodoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TodoError::Io(e) => Some(e),
+            TodoError::Database(e) => Some(e),
+            TodoError::Watch(e) => Some(e),
+            TodoError::Git(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TodoError {
+    fn from(e: io::Error) -> Self {
+        TodoError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for TodoError {
+    fn from(e: rusqlite::Error) -> Self {
+        TodoError::Database(e)
+    }
+}
+
+impl From<serde_json::Error> for TodoError {
+    fn from(e: serde_json::Error) -> Self {
+        if e.is_io() {
+            TodoError::Io(e.into())
+        } else {
+            TodoError::Parse { line: e.line(), message: e.to_string() }
+        }
+    }
+}
+
+impl From<git2::Error> for TodoError {
+    fn from(e: git2::Error) -> Self {
+        TodoError::Git(e)
+    }
+}
+
+impl From<notify::Error> for TodoError {
+    fn from(e: notify::Error) -> Self {
+        TodoError::Watch(e)
+    }
+}
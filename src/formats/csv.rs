@@ -0,0 +1,61 @@
+// Reads RFC 4180 CSV: fields may be quoted, with `""` for a quote, and
+// quoted fields may span lines
+pub fn parse(contents: &str) -> Vec<Vec<String>> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) => {},
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            },
+            (c, _) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    // Blank lines aren't records
+    records.retain(|record| record.len() > 1 || record.first().is_some_and(|field| !field.is_empty()));
+    records
+}
+
+pub fn write_record(contents: &mut String, fields: &[&str]) {
+    let fields = fields.iter().map(|field| match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    });
+    contents.push_str(&fields.collect::<Vec<_>>().join(","));
+    contents.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_records_read_back() {
+        let mut contents = String::new();
+        write_record(&mut contents, &["Task", "Notes"]);
+        write_record(&mut contents, &["Call mom, then dad", "Say \"hi\"\nand hang up"]);
+        assert_eq!(contents, "Task,Notes\r\n\"Call mom, then dad\",\"Say \"\"hi\"\"\nand hang up\"\r\n");
+        assert_eq!(parse(&contents), [vec!["Task", "Notes"], vec!["Call mom, then dad", "Say \"hi\"\nand hang up"]]);
+    }
+
+    #[test]
+    fn blank_lines_and_empty_fields() {
+        assert_eq!(parse("a,,c\n\nd\n"), [vec!["a", "", "c"], vec!["d"]]);
+    }
+}
@@ -0,0 +1,261 @@
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+use crate::todo::{clean_notes, Todo, DATE_FORMAT, PARENT_TAG};
+
+// iCalendar lines are folded after this many octets (RFC 5545, 3.1)
+const LINE_LIMIT: usize = 75;
+
+// The list as VTODO components. The task id is the UID, so importing the
+// file again updates the tasks instead of adding them twice.
+pub fn serialize(todos: &[&Todo]) -> String {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut lines = vec![
+        String::from("BEGIN:VCALENDAR"),
+        String::from("VERSION:2.0"),
+        String::from("PRODID:-//todo-rs//todo-rs//EN"),
+    ];
+    for todo in todos {
+        lines.push(String::from("BEGIN:VTODO"));
+        lines.push(format!("UID:{}", todo.id));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        // The due date and the parent have properties of their own
+        let mut summary = (*todo).clone();
+        summary.set_tag("due", None);
+        summary.set_tag(PARENT_TAG, None);
+        lines.push(format!("SUMMARY:{}", escape(&summary.description)));
+        if !todo.notes.is_empty() {
+            lines.push(format!("DESCRIPTION:{}", escape(&todo.notes)));
+        }
+        lines.push(format!("STATUS:{}", if todo.is_done() { "COMPLETED" } else { "NEEDS-ACTION" }));
+        if let Some(date) = todo.creation_date {
+            lines.push(format!("CREATED:{}", to_utc(date)));
+        }
+        if let Some(date) = todo.completion_date {
+            lines.push(format!("COMPLETED:{}", to_utc(date)));
+        }
+        if let Some(due) = todo.due_date() {
+            lines.push(format!("DUE;VALUE=DATE:{}", due.format("%Y%m%d")));
+        }
+        if let Some(parent) = todo.parent() {
+            lines.push(format!("RELATED-TO;RELTYPE=PARENT:{}", parent));
+        }
+        if let Some(priority) = todo.priority {
+            lines.push(format!("PRIORITY:{}", to_ical_priority(priority)));
+        }
+        lines.push(String::from("END:VTODO"));
+    }
+    lines.push(String::from("END:VCALENDAR"));
+
+    let mut contents = String::new();
+    for line in lines {
+        contents.push_str(&fold(&line));
+    }
+    contents
+}
+
+// Every VTODO of the file, other components are skipped
+pub fn parse(contents: &str) -> Vec<Todo> {
+    let mut todos = vec![];
+    let mut current: Option<Vec<(String, String, String)>> = None;
+    // Components nested in a VTODO, such as a VALARM
+    let mut nested = 0;
+    for line in unfold(contents) {
+        let Some((name, params, value)) = split_property(&line) else {
+            continue;
+        };
+        match (name.as_str(), value.to_ascii_uppercase().as_str(), current.is_some()) {
+            ("BEGIN", "VTODO", false) => current = Some(vec![]),
+            ("BEGIN", _, true) => nested += 1,
+            ("END", "VTODO", true) if nested == 0 => {
+                if let Some(todo) = current.take().and_then(|properties| to_todo(&properties)) {
+                    todos.push(todo);
+                }
+            },
+            ("END", _, true) => nested -= 1,
+            (_, _, true) if nested == 0 => {
+                if let Some(properties) = current.as_mut() {
+                    properties.push((name, params, value));
+                }
+            },
+            _ => {},
+        }
+    }
+    todos
+}
+
+fn to_todo(properties: &[(String, String, String)]) -> Option<Todo> {
+    let property = |name: &str| properties.iter().find(|(n, _, _)| n == name).map(|(_, _, value)| value.as_str());
+    let summary = unescape(property("SUMMARY")?).replace(['\r', '\n'], " ");
+    if summary.trim().is_empty() {
+        return None;
+    }
+
+    let mut todo = Todo::default();
+    todo.set_description(summary.trim());
+    // Ids are a single word of the todo.txt line
+    todo.id = property("UID").map(|uid| uid.split_whitespace().collect::<Vec<_>>().join("_")).unwrap_or_default();
+    todo.creation_date = property("CREATED").and_then(parse_date);
+    todo.priority = property("PRIORITY").and_then(from_ical_priority);
+    todo.notes = property("DESCRIPTION").map(|description| clean_notes(&unescape(description))).unwrap_or_default();
+    if let Some(due) = property("DUE").and_then(parse_date) {
+        todo.set_tag("due", Some(&due.format(DATE_FORMAT).to_string()));
+    }
+    // PARENT is the default relationship, other types are left out
+    let parent = properties.iter().find(|(name, params, _)| {
+        name == "RELATED-TO" && params.split(';').all(|param| !param.to_ascii_uppercase().starts_with("RELTYPE=") || param.eq_ignore_ascii_case("RELTYPE=PARENT"))
+    });
+    if let Some((_, _, parent)) = parent {
+        todo.set_tag(PARENT_TAG, Some(&parent.split_whitespace().collect::<Vec<_>>().join("_")));
+    }
+    // Cancelled tasks won't be worked on either
+    if matches!(property("STATUS"), Some("COMPLETED" | "CANCELLED")) || property("COMPLETED").is_some() {
+        todo.mark_as_done();
+        todo.completion_date = property("COMPLETED").and_then(parse_date).or(todo.completion_date);
+    }
+    Some(todo)
+}
+
+// CREATED and COMPLETED have to be UTC times. Local noon reads back as the
+// same date here and in the time zones around, and is never skipped by DST.
+fn to_utc(date: NaiveDate) -> String {
+    let noon = date.and_hms_opt(12, 0, 0).unwrap_or_default();
+    let time = Local.from_local_datetime(&noon).earliest().map(|time| time.with_timezone(&Utc)).unwrap_or_else(|| noon.and_utc());
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// A DATE or DATE-TIME value as a local date. Times without a `Z` are
+// floating or in a TZID, their date is taken as is.
+pub(super) fn parse_date(value: &str) -> Option<NaiveDate> {
+    if let Some(utc) = value.strip_suffix('Z') {
+        let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+        return Some(time.and_utc().with_timezone(&Local).date_naive());
+    }
+    NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()
+}
+
+// 1 is the highest iCalendar priority and 9 the lowest, 0 means none
+fn to_ical_priority(priority: char) -> u8 {
+    (priority as u8 - b'A' + 1).min(9)
+}
+
+fn from_ical_priority(value: &str) -> Option<char> {
+    match value.trim().parse::<u8>() {
+        Ok(n @ 1..=9) => Some((b'A' + n - 1) as char),
+        _ => None,
+    }
+}
+
+// `NAME;PARAM=x:value` into the upper-cased name, the parameters and the value
+fn split_property(line: &str) -> Option<(String, String, String)> {
+    // Parameter values may be quoted and contain `:`
+    let mut quoted = false;
+    let colon = line.char_indices().find(|&(_, c)| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        c == ':' && !quoted
+    })?.0;
+    let (name, params) = line[..colon].split_once(';').unwrap_or((&line[..colon], ""));
+    Some((name.to_ascii_uppercase(), params.to_string(), line[colon + 1..].to_string()))
+}
+
+// Joins folded lines, which continue with a space or a tab
+fn unfold(contents: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in contents.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        // Don't split a UTF-8 sequence
+        if width + c.len_utf8() > LINE_LIMIT {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace(';', "\\;").replace(',', "\\,").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tasks_survive_an_export_and_import() {
+        let mut todo = Todo::parse("(A) 2023-05-01 Call mom, then dad; soon @phone due:2023-05-03 id:call");
+        todo.id = String::from("call");
+        todo.notes = String::from("Her number\nis in the address book");
+        let mut done = Todo::parse("Feed the cat parent:call");
+        done.id = String::from("feed");
+        done.mark_as_done();
+        done.completion_date = NaiveDate::from_ymd_opt(2023, 5, 2);
+
+        let contents = serialize(&[&todo, &done]);
+        assert!(contents.contains("RELATED-TO;RELTYPE=PARENT:call\r\n"));
+        assert!(!contents.contains("parent:"));
+        let parsed = parse(&contents);
+        let [call, feed] = &parsed[..] else {
+            panic!("expected two tasks");
+        };
+        assert_eq!(call.id, "call");
+        assert_eq!(call.priority, Some('A'));
+        assert_eq!(call.creation_date, NaiveDate::from_ymd_opt(2023, 5, 1));
+        assert_eq!(call.due_date(), NaiveDate::from_ymd_opt(2023, 5, 3));
+        assert!(call.description.starts_with("Call mom, then dad; soon @phone"));
+        assert_eq!(call.notes, todo.notes);
+        assert!(feed.is_done());
+        assert_eq!(feed.description, "Feed the cat parent:call");
+        assert_eq!(feed.parent(), Some("call"));
+        assert_eq!(feed.completion_date, NaiveDate::from_ymd_opt(2023, 5, 2));
+    }
+
+    #[test]
+    fn long_lines_are_folded_and_unfolded() {
+        let line = format!("SUMMARY:{}", "é".repeat(60));
+        let folded = fold(&line);
+        assert!(folded.split("\r\n").all(|part| part.len() <= LINE_LIMIT));
+        assert_eq!(unfold(&folded), [line]);
+    }
+
+    #[test]
+    fn other_components_are_skipped() {
+        let contents = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Meeting\r\nEND:VEVENT\r\n\
+                        BEGIN:VTODO\r\nUID:a\r\nSUMMARY:Task\r\nBEGIN:VALARM\r\nDESCRIPTION:Alarm\r\nEND:VALARM\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        let parsed = parse(contents);
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].description, "Task");
+        assert_eq!(parsed[0].notes, "");
+    }
+}
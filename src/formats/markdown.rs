@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+
+use crate::todo::{new_id, tree_order, Todo, PARENT_TAG};
+
+// GitHub-style task lists:
+//   - [ ] Call mom +family
+//     - [x] Find her number
+// Any bullet (`-`, `*`, `+`, `1.`) works and nested items are subtasks of
+// the item above them. Everything that isn't a checklist item, fenced code
+// included, is skipped.
+pub fn parse(contents: &str) -> Vec<Todo> {
+    let mut todos: Vec<Todo> = vec![];
+    let mut in_code = false;
+    // Indentation and index of the items the next one may be nested in
+    let mut open: Vec<(usize, usize)> = vec![];
+    for line in contents.lines() {
+        let indent = line.chars().take_while(|c| c.is_whitespace()).map(|c| if c == '\t' { 4 } else { 1 }).sum::<usize>();
+        let line = line.trim();
+        if line.starts_with("```") || line.starts_with("~~~") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        let Some((done, text)) = parse_item(line) else {
+            // A heading or paragraph ends the list above it
+            if !line.is_empty() && indent == 0 {
+                open.clear();
+            }
+            continue;
+        };
+        // The text is read as a todo.txt line, so priorities and tags survive
+        // a round trip
+        let mut todo = Todo::parse(text);
+        if todo.description.is_empty() {
+            continue;
+        }
+        if done {
+            todo.mark_as_done();
+        }
+        while open.last().is_some_and(|&(parent_indent, _)| parent_indent >= indent) {
+            open.pop();
+        }
+        if let Some(&(_, index)) = open.last() {
+            let parent = &mut todos[index];
+            if parent.id.is_empty() {
+                parent.id = new_id();
+            }
+            todo.set_tag(PARENT_TAG, Some(&parent.id));
+        }
+        open.push((indent, todos.len()));
+        todos.push(todo);
+    }
+    todos
+}
+
+// Subtasks are nested under their parent instead of keeping their
+// `parent:` tag
+pub fn serialize(todos: &[&Todo]) -> String {
+    let mut contents = String::new();
+    for (todo, depth) in tree_order(todos, &HashSet::new()) {
+        let check = if todo.is_done() { "x" } else { " " };
+        let mut item = todo.clone();
+        item.set_tag(PARENT_TAG, None);
+        let indent = "  ".repeat(depth);
+        match todo.priority {
+            Some(priority) => contents.push_str(&format!("{}- [{}] ({}) {}\n", indent, check, priority, item.description)),
+            None => contents.push_str(&format!("{}- [{}] {}\n", indent, check, item.description)),
+        }
+    }
+    contents
+}
+
+// `- [x] text` into whether it's checked and its text
+fn parse_item(line: &str) -> Option<(bool, &str)> {
+    let rest = match line.strip_prefix(['-', '*', '+']) {
+        Some(rest) => rest,
+        None => {
+            let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            line[digits..].strip_prefix(['.', ')']).filter(|_| digits > 0)?
+        },
+    };
+    let rest = rest.strip_prefix(' ')?.trim_start();
+    let done = match rest.get(..3)? {
+        "[ ]" => false,
+        "[x]" | "[X]" => true,
+        _ => return None,
+    };
+    let text = &rest[3..];
+    if !text.is_empty() && !text.starts_with(' ') {
+        return None;
+    }
+    Some((done, text.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_lists_read_back_as_written() {
+        let contents = "- [ ] (A) Call mom +family due:2023-05-03\n  - [x] Find her number\n    - [ ] Look in the drawer\n- [ ] Feed the cat\n";
+        let mut todos = parse(contents);
+        // As importing does
+        for todo in todos.iter_mut().filter(|todo| todo.id.is_empty()) {
+            todo.id = new_id();
+        }
+        assert_eq!(todos[0].priority, Some('A'));
+        assert!(todos[0].has_tag("+family"));
+        assert_eq!(todos[1].parent(), Some(todos[0].id.as_str()));
+        assert_eq!(todos[2].parent(), Some(todos[1].id.as_str()));
+        assert_eq!(todos[3].parent(), None);
+        assert_eq!(serialize(&todos.iter().collect::<Vec<_>>()), contents);
+    }
+
+    #[test]
+    fn only_checklist_items_are_read() {
+        let contents = "# Chores\n\n* [X] Done\n1. [ ] Numbered\n- plain item\n```\n- [ ] in code\n```\n";
+        let todos = parse(contents);
+        assert_eq!(todos.iter().map(|todo| todo.description.as_str()).collect::<Vec<_>>(), ["Done", "Numbered"]);
+        assert!(todos[0].is_done());
+    }
+}
@@ -0,0 +1,106 @@
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::TodoError;
+use crate::storage::write_atomically;
+use crate::todo::Todo;
+
+mod csv;
+mod ics;
+mod markdown;
+mod report;
+mod taskwarrior;
+mod todoist;
+
+// Formats tasks are imported from and exported to. Unlike storage backends
+// these are one-off copies and the file isn't watched.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    // iCalendar VTODOs, which keep the task ids as UIDs
+    Ics,
+    // `task export`, import only
+    Taskwarrior,
+    // Project templates and API backups, import only
+    TodoistCsv,
+    TodoistJson,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Markdown => "Markdown",
+            Format::Ics => "iCalendar",
+            Format::Taskwarrior => "Taskwarrior",
+            Format::TodoistCsv => "Todoist CSV",
+            Format::TodoistJson => "Todoist JSON",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Tasks read from a file, not added to any list yet
+#[derive(Default)]
+pub struct Parsed {
+    // Tasks without an id get one from `Todos::import`
+    pub todos: Vec<Todo>,
+    // Entries that aren't tasks to import, such as deleted tasks or sections
+    pub skipped: usize,
+}
+
+// Reads the tasks of a file in one of the supported formats. JSON files are
+// told apart by their contents.
+pub fn import(path: &Path) -> Result<(Format, Parsed), TodoError> {
+    let contents = fs::read_to_string(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => TodoError::NotFound(path.display().to_string()),
+        _ => TodoError::Io(e),
+    })?;
+    match extension(path).as_str() {
+        "md" | "markdown" => Ok((Format::Markdown, Parsed { todos: markdown::parse(&contents), skipped: 0 })),
+        "ics" | "ical" => Ok((Format::Ics, Parsed { todos: ics::parse(&contents), skipped: 0 })),
+        "csv" => {
+            let project = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            Ok((Format::TodoistCsv, todoist::parse_csv(&contents, &project)))
+        },
+        "json" => match serde_json::from_str::<Value>(&contents) {
+            Ok(value) if is_todoist(&value) => Ok((Format::TodoistJson, todoist::parse_json(&value))),
+            // Older Taskwarrior versions write one object per line, which
+            // isn't a JSON document as a whole
+            _ => Ok((Format::Taskwarrior, taskwarrior::parse(&contents)?)),
+        },
+        _ => Err(TodoError::Unsupported(format!(
+            "can't tell the format of {}, expected a .md, .ics, .json (Taskwarrior, Todoist) or .csv (Todoist) file",
+            path.display(),
+        ))),
+    }
+}
+
+// Writes tasks to a file, in the format its extension names. `title`
+// heads the HTML report.
+pub fn export(path: &Path, todos: &[&Todo], title: &str) -> Result<(), TodoError> {
+    let contents = match extension(path).as_str() {
+        "md" | "markdown" => markdown::serialize(todos),
+        "ics" | "ical" => ics::serialize(todos),
+        "html" | "htm" => report::html(title, todos),
+        "csv" => report::csv(todos),
+        _ => return Err(TodoError::Unsupported(format!("can't export to {}, expected a .md, .ics, .html or .csv file", path.display()))),
+    };
+    write_atomically(path, contents.as_bytes(), 0)?;
+    Ok(())
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase()
+}
+
+// Todoist tasks have a `content`, Taskwarrior ones a `description`
+fn is_todoist(value: &Value) -> bool {
+    match value {
+        Value::Object(object) => object.contains_key("items"),
+        Value::Array(items) => items.first().is_some_and(|item| item.get("content").is_some()),
+        _ => false,
+    }
+}
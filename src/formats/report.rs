@@ -0,0 +1,101 @@
+use chrono::{Local, NaiveDate};
+
+use super::csv;
+use crate::todo::{Todo, DATE_FORMAT, PARENT_TAG};
+
+const STYLE: &str = "\
+body { font-family: system-ui, sans-serif; margin: 2em auto; max-width: 60em; color: #222; }
+h1 { margin-bottom: 0.2em; }
+.summary { color: #666; }
+.progress { background: #eee; border-radius: 4px; height: 0.6em; margin-bottom: 2em; }
+.progress div { background: #4caf50; border-radius: 4px; height: 100%; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 2em; }
+th, td { text-align: left; padding: 0.4em 0.6em; border-bottom: 1px solid #ddd; }
+th { background: #f5f5f5; }
+.done td { color: #888; }
+.done .task { text-decoration: line-through; }
+.priority { font-weight: bold; }
+.overdue { color: #c62828; font-weight: bold; }
+.tag { color: #1565c0; }
+";
+
+// A page that can be opened or mailed on its own: the open tasks, then the
+// done ones, with a progress bar
+pub fn html(title: &str, todos: &[&Todo]) -> String {
+    let (done, open): (Vec<&Todo>, Vec<&Todo>) = todos.iter().partition(|todo| todo.is_done());
+    let percent = match todos.len() {
+        0 => 0,
+        total => done.len() * 100 / total,
+    };
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n\
+         <h1>{title}</h1>\n<p class=\"summary\">{done} of {total} done, generated {generated}</p>\n\
+         <div class=\"progress\"><div style=\"width: {percent}%\"></div></div>\n",
+        title = escape(title),
+        done = done.len(),
+        total = todos.len(),
+        generated = Local::now().format("%Y-%m-%d %H:%M"),
+    );
+    for (heading, group) in [("Open", &open), ("Done", &done)] {
+        if group.is_empty() {
+            continue;
+        }
+        page.push_str(&format!("<h2>{} ({})</h2>\n<table>\n", heading, group.len()));
+        page.push_str("<tr><th>Priority</th><th>Task</th><th>Projects</th><th>Contexts</th><th>Due</th><th>Created</th><th>Completed</th></tr>\n");
+        for todo in group.iter() {
+            let due = todo.due_date();
+            let overdue = !todo.is_done() && due.is_some_and(|due| due < Local::now().date_naive());
+            page.push_str(&format!(
+                "<tr{}><td class=\"priority\">{}</td><td class=\"task\">{}</td><td class=\"tag\">{}</td><td class=\"tag\">{}</td><td{}>{}</td><td>{}</td><td>{}</td></tr>\n",
+                if todo.is_done() { " class=\"done\"" } else { "" },
+                todo.priority.map(String::from).unwrap_or_default(),
+                escape(&description(todo)),
+                escape(&todo.projects.join(" ")),
+                escape(&todo.contexts.join(" ")),
+                if overdue { " class=\"overdue\"" } else { "" },
+                date(due),
+                date(todo.creation_date),
+                date(todo.completion_date),
+            ));
+        }
+        page.push_str("</table>\n");
+    }
+    page.push_str("</body>\n</html>\n");
+    page
+}
+
+// One row per task, for spreadsheets
+pub fn csv(todos: &[&Todo]) -> String {
+    let mut contents = String::new();
+    csv::write_record(&mut contents, &["status", "priority", "description", "projects", "contexts", "due", "created", "completed", "id", "parent"]);
+    for todo in todos {
+        csv::write_record(&mut contents, &[
+            if todo.is_done() { "done" } else { "open" },
+            &todo.priority.map(String::from).unwrap_or_default(),
+            &description(todo),
+            &todo.projects.join(" "),
+            &todo.contexts.join(" "),
+            &date(todo.due_date()),
+            &date(todo.creation_date),
+            &date(todo.completion_date),
+            &todo.id,
+            todo.parent().unwrap_or_default(),
+        ]);
+    }
+    contents
+}
+
+// Subtasks are linked by id in the CSV, and the id means nothing in the page
+fn description(todo: &Todo) -> String {
+    let mut todo = todo.clone();
+    todo.set_tag(PARENT_TAG, None);
+    todo.description
+}
+
+fn date(date: Option<NaiveDate>) -> String {
+    date.map(|date| date.format(DATE_FORMAT).to_string()).unwrap_or_default()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
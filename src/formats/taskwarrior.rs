@@ -0,0 +1,84 @@
+use serde::Deserialize;
+
+use super::ics::parse_date;
+use super::Parsed;
+use crate::todo::{clean_notes, Todo, DATE_FORMAT};
+
+// A task as written by `task export`. Timestamps are basic ISO 8601 in UTC,
+// as in iCalendar.
+#[derive(Deserialize)]
+struct Task {
+    #[serde(default)]
+    uuid: String,
+    description: String,
+    #[serde(default)]
+    status: String,
+    entry: Option<String>,
+    end: Option<String>,
+    due: Option<String>,
+    priority: Option<String>,
+    project: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+}
+
+#[derive(Deserialize)]
+struct Annotation {
+    description: String,
+}
+
+// `task export` writes a JSON array, older versions one object per line
+pub fn parse(contents: &str) -> Result<Parsed, serde_json::Error> {
+    let tasks: Vec<Task> = match contents.trim_start().starts_with('[') {
+        true => serde_json::from_str(contents)?,
+        false => contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line.trim().trim_end_matches(',')))
+            .collect::<Result<_, _>>()?,
+    };
+
+    let mut parsed = Parsed::default();
+    for task in tasks {
+        // Recurring tasks are templates, their instances are exported as
+        // pending tasks of their own
+        if matches!(task.status.as_str(), "deleted" | "recurring") || task.description.trim().is_empty() {
+            parsed.skipped += 1;
+            continue;
+        }
+        let mut words = vec![task.description.trim().to_string()];
+        if let Some(project) = task.project.filter(|project| !project.is_empty()) {
+            words.push(format!("+{}", tag_word(&project)));
+        }
+        words.extend(task.tags.iter().map(|tag| format!("@{}", tag_word(tag))));
+        if let Some(due) = task.due.as_deref().and_then(parse_date) {
+            words.push(format!("due:{}", due.format(DATE_FORMAT)));
+        }
+
+        let mut todo = Todo::default();
+        todo.set_description(&words.join(" "));
+        todo.id = task.uuid;
+        todo.creation_date = task.entry.as_deref().and_then(parse_date);
+        // Annotations become the notes, one per line
+        todo.notes = clean_notes(&task.annotations.iter().map(|annotation| annotation.description.trim()).collect::<Vec<_>>().join("\n"));
+        todo.priority = match task.priority.as_deref() {
+            Some("H") => Some('A'),
+            Some("M") => Some('B'),
+            Some("L") => Some('C'),
+            _ => None,
+        };
+        if task.status == "completed" {
+            todo.mark_as_done();
+            todo.completion_date = task.end.as_deref().and_then(parse_date).or(todo.completion_date);
+        }
+        parsed.todos.push(todo);
+    }
+    Ok(parsed)
+}
+
+// Projects and tags become single words of the description
+pub(super) fn tag_word(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join("-")
+}
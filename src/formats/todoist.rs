@@ -0,0 +1,115 @@
+use chrono::NaiveDate;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use super::taskwarrior::tag_word;
+use super::{csv, Parsed};
+use crate::todo::{Todo, DATE_FORMAT};
+
+// A project exported as a CSV template, one row per task, section or note:
+//   TYPE,CONTENT,DESCRIPTION,PRIORITY,INDENT,AUTHOR,RESPONSIBLE,DATE,...
+// The file is named after the project and labels are `@label` words of the
+// content already. Templates hold open tasks only and have no ids.
+pub fn parse_csv(contents: &str, project: &str) -> Parsed {
+    let mut parsed = Parsed::default();
+    let mut records = csv::parse(contents).into_iter();
+    let Some(header) = records.next() else {
+        return parsed;
+    };
+    let column = |name: &str| header.iter().position(|field| field.trim().eq_ignore_ascii_case(name));
+    let (Some(kind), Some(content)) = (column("TYPE"), column("CONTENT")) else {
+        return parsed;
+    };
+    let (priority, date) = (column("PRIORITY"), column("DATE"));
+
+    for record in records {
+        let field = |index: Option<usize>| index.and_then(|index| record.get(index)).map(|field| field.trim()).unwrap_or("");
+        if !field(Some(kind)).eq_ignore_ascii_case("task") || field(Some(content)).is_empty() {
+            parsed.skipped += 1;
+            continue;
+        }
+        // Priorities are 1 (p1, the highest) to 4 (p4, the default) here
+        let priority = match field(priority) {
+            "1" => Some('A'),
+            "2" => Some('B'),
+            "3" => Some('C'),
+            _ => None,
+        };
+        parsed.todos.push(to_todo(field(Some(content)), Some(project), &[], priority, field(date), false, String::new()));
+    }
+    parsed
+}
+
+// The JSON of the sync API, `{"items": [...], "projects": [...]}`, or the
+// array of tasks the REST API returns
+pub fn parse_json(contents: &Value) -> Parsed {
+    let (items, projects) = match contents {
+        Value::Array(items) => (items.as_slice(), HashMap::new()),
+        Value::Object(object) => {
+            let projects = object.get("projects").and_then(Value::as_array).map_or_else(HashMap::new, |projects| {
+                projects.iter().filter_map(|project| Some((id(project.get("id")?), project.get("name")?.as_str()?.to_string()))).collect()
+            });
+            (object.get("items").and_then(Value::as_array).map_or(&[][..], Vec::as_slice), projects)
+        },
+        _ => (&[][..], HashMap::new()),
+    };
+
+    let mut parsed = Parsed::default();
+    for item in items {
+        let content = item.get("content").and_then(Value::as_str).unwrap_or("");
+        if content.trim().is_empty() || item.get("is_deleted").is_some_and(truthy) {
+            parsed.skipped += 1;
+            continue;
+        }
+        let done = item.get("checked").or(item.get("is_completed")).is_some_and(truthy);
+        let project = item.get("project_id").map(id).and_then(|project| projects.get(&project)).map(String::as_str);
+        // Labels are names, older backups have ids which can't be resolved
+        let labels = item.get("labels").and_then(Value::as_array).map_or_else(Vec::new, |labels| {
+            labels.iter().filter_map(|label| label.as_str().map(String::from)).collect()
+        });
+        // The API counts the other way round: 4 is p1, the highest
+        let priority = match item.get("priority").and_then(Value::as_u64) {
+            Some(4) => Some('A'),
+            Some(3) => Some('B'),
+            Some(2) => Some('C'),
+            _ => None,
+        };
+        let due = item.get("due").and_then(|due| due.get("date")).and_then(Value::as_str).unwrap_or("");
+        let id = item.get("id").map(id).unwrap_or_default();
+        parsed.todos.push(to_todo(content, project, &labels, priority, due, done, id));
+    }
+    parsed
+}
+
+fn to_todo(content: &str, project: Option<&str>, labels: &[String], priority: Option<char>, due: &str, done: bool, id: String) -> Todo {
+    let mut words = vec![content.trim().to_string()];
+    if let Some(project) = project.filter(|project| !project.is_empty()) {
+        words.push(format!("+{}", tag_word(project)));
+    }
+    words.extend(labels.iter().map(|label| format!("@{}", tag_word(label))));
+    // Only exact dates, recurring ones such as "every day" are dropped
+    if let Some(due) = due.get(..10).and_then(|date| NaiveDate::parse_from_str(date, DATE_FORMAT).ok()) {
+        words.push(format!("due:{}", due.format(DATE_FORMAT)));
+    }
+
+    let mut todo = Todo::default();
+    todo.set_description(&words.join(" "));
+    todo.id = id;
+    todo.priority = priority;
+    if done {
+        todo.mark_as_done();
+    }
+    todo
+}
+
+// Ids are numbers in older backups and strings in newer ones
+fn id(value: &Value) -> String {
+    match value {
+        Value::String(id) => id.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    value.as_bool().unwrap_or_else(|| value.as_u64().is_some_and(|n| n != 0))
+}
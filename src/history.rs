@@ -0,0 +1,113 @@
+use chrono::{DateTime, Local};
+use git2::{FileMode, Oid, Repository, Signature};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::TodoError;
+
+// Versions older than this aren't listed in the history view
+const MAX_VERSIONS: usize = 200;
+
+// The repository lives in the lists directory under this name
+const HISTORY_DIR: &str = ".todo-rs-history";
+
+// A bare git repository of its own in the lists directory, so that a
+// repository the directory is part of, its branch and its index are left
+// alone. Every save of a list commits its file, the trees hold nothing but
+// list files.
+pub struct History {
+    repo: Repository,
+    dir: PathBuf,
+}
+
+pub struct Version {
+    pub id: Oid,
+    pub time: DateTime<Local>,
+    pub message: String,
+}
+
+impl History {
+    // Opens the repository, creating it on first use
+    pub fn open(dir: &Path) -> Result<Self, TodoError> {
+        let path = dir.join(HISTORY_DIR);
+        let repo = match Repository::open_bare(&path) {
+            Ok(repo) => repo,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => Repository::init_bare(&path)?,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            repo,
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    // Commits the current contents of `file`, unless they didn't change
+    pub fn commit(&self, file: &Path, message: &str) -> Result<(), TodoError> {
+        let name = self.relative(file)?;
+        let parent = match self.repo.head() {
+            Ok(head) => Some(head.peel_to_commit()?),
+            Err(e) if matches!(e.code(), git2::ErrorCode::UnbornBranch | git2::ErrorCode::NotFound) => None,
+            Err(e) => return Err(e.into()),
+        };
+        // The other lists keep the versions they had
+        let previous = parent.as_ref().map(|parent| parent.tree()).transpose()?;
+        let mut builder = self.repo.treebuilder(previous.as_ref())?;
+        let blob = self.repo.blob(&fs::read(file)?)?;
+        builder.insert(&name, blob, FileMode::Blob.into())?;
+        let tree = self.repo.find_tree(builder.write()?)?;
+
+        if parent.as_ref().is_some_and(|parent| parent.tree_id() == tree.id()) {
+            return Ok(());
+        }
+        // Don't depend on a git identity being configured
+        let signature = self.repo.signature().or_else(|_| Signature::now("todo-rs", "todo-rs@localhost"))?;
+        let parents = parent.iter().collect::<Vec<_>>();
+        self.repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)?;
+        Ok(())
+    }
+
+    // Commits that changed `file`, newest first
+    pub fn versions(&self, file: &Path) -> Result<Vec<Version>, TodoError> {
+        let name = self.relative(file)?;
+        let mut walk = self.repo.revwalk()?;
+        if walk.push_head().is_err() {
+            // Nothing committed yet
+            return Ok(vec![]);
+        }
+
+        let mut versions = vec![];
+        for id in walk {
+            let commit = self.repo.find_commit(id?)?;
+            let blob = commit.tree()?.get_path(&name).ok().map(|entry| entry.id());
+            let before = commit.parent(0).ok().and_then(|parent| parent.tree().ok()?.get_path(&name).ok().map(|entry| entry.id()));
+            if blob.is_none() || blob == before {
+                continue;
+            }
+            versions.push(Version {
+                id: commit.id(),
+                time: DateTime::from_timestamp(commit.time().seconds(), 0).unwrap_or_default().with_timezone(&Local),
+                message: commit.summary().unwrap_or_default().to_string(),
+            });
+            if versions.len() == MAX_VERSIONS {
+                break;
+            }
+        }
+        Ok(versions)
+    }
+
+    // What `file` contained as of the given commit
+    pub fn contents(&self, file: &Path, id: Oid) -> Result<Vec<u8>, TodoError> {
+        let name = self.relative(file)?;
+        let entry = self.repo.find_commit(id)?.tree()?.get_path(&name)?;
+        Ok(self.repo.find_blob(entry.id())?.content().to_vec())
+    }
+
+    fn relative(&self, file: &Path) -> Result<PathBuf, TodoError> {
+        let name = file.file_name().ok_or_else(|| TodoError::NotFound(format!("list {}", file.display())))?;
+        let parent = file.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        if parent.canonicalize()? != self.dir.canonicalize()? {
+            return Err(TodoError::NotFound(format!("{} in {}", file.display(), self.dir.display())));
+        }
+        Ok(PathBuf::from(name))
+    }
+}
@@ -0,0 +1,310 @@
+use chrono::Utc;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::TodoError;
+use crate::merge::merge;
+use crate::storage::{escape, unescape, with_suffix};
+use crate::todo::Todo;
+
+// The journal is opt-in, by default every change rewrites the list and is
+// only logged to the archive
+pub const DEFAULT_COMPACT_AFTER: usize = 0;
+
+// A change to the list, as recorded in the journal
+pub enum Operation {
+    Add(Todo),
+    Toggle(Todo),
+    Edit(Todo),
+    Delete(String),
+}
+
+impl Operation {
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::Add(_) => "add",
+            Operation::Toggle(_) => "toggle",
+            Operation::Edit(_) => "edit",
+            Operation::Delete(_) => "delete",
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            Operation::Add(todo) | Operation::Toggle(todo) | Operation::Edit(todo) => &todo.id,
+            Operation::Delete(id) => id,
+        }
+    }
+}
+
+struct Entry {
+    op: String,
+    id: String,
+    // None for a deletion
+    todo: Option<Todo>,
+    before: Option<Todo>,
+}
+
+// What replaying the journal ran into
+#[derive(Default)]
+pub struct Replayed {
+    pub malformed: Vec<TodoError>,
+    // Operations on tasks that no longer exist in the list
+    pub conflicts: Vec<String>,
+}
+
+// Append-only log of operations kept next to the list in `<file>.journal`,
+// one per line:
+//
+//   2023-05-01T10:00:00Z<TAB>toggle<TAB><id><TAB><task as todo.txt><TAB><task before>
+//
+// Changes are appended here instead of rewriting the whole list. Replaying
+// is idempotent, each entry carries the full resulting task, so entries
+// that already made it into the list are harmless. Compacting moves the
+// entries to `<file>.journal.archive`, which keeps the audit trail; changes
+// saved with the whole list go straight there.
+pub struct Journal {
+    path: PathBuf,
+    archive: PathBuf,
+    entries: usize,
+}
+
+impl Journal {
+    pub fn new(list: &Path) -> Self {
+        Self {
+            path: with_suffix(list, ".journal"),
+            archive: with_suffix(list, ".journal.archive"),
+            entries: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    // `before` is the task as it was saved or journaled last, none for a
+    // new one
+    pub fn append(&mut self, operation: &Operation, before: Option<&Todo>) -> Result<(), TodoError> {
+        write_line(&self.path, &line(operation, before))?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    // Logs a change that was saved with the whole list, so that only the
+    // audit trail needs it
+    pub fn log(&self, operation: &Operation, before: Option<&Todo>) -> Result<(), TodoError> {
+        write_line(&self.archive, &line(operation, before))
+    }
+
+    // Applies the journal to `list`, the tasks loaded from the file. Each
+    // entry carries the task as it was before the change, which gives back
+    // the list the journal started from. When the file has changed since,
+    // the entries are merged with those changes instead of laid over them.
+    pub fn replay(&mut self, list: &[Todo]) -> Result<(Vec<Todo>, Replayed), TodoError> {
+        let mut replayed = Replayed::default();
+        self.entries = 0;
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((list.to_vec(), replayed)),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = vec![];
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            self.entries += 1;
+            let line_number = index + 1;
+            let fields = line.splitn(5, '\t').collect::<Vec<_>>();
+            let [_, op, id, payload, ..] = fields[..] else {
+                replayed.malformed.push(TodoError::Parse { line: line_number, message: "journal entry has missing fields".to_string() });
+                continue;
+            };
+            if !matches!(op, "add" | "toggle" | "edit" | "delete") {
+                replayed.malformed.push(TodoError::Parse { line: line_number, message: format!("journal: unknown operation `{}`", op) });
+                continue;
+            }
+            let parse = |text: &str| match text {
+                "" => None,
+                text => Some(Todo { id: id.to_string(), ..Todo::parse(&unescape(text)) }),
+            };
+            let (todo, before) = (parse(payload), parse(fields.get(4).copied().unwrap_or_default()));
+            entries.push(Entry { op: op.to_string(), id: id.to_string(), todo, before });
+        }
+
+        // The list as it was when the first entry about each task was written.
+        // Entries from before tasks were recorded leave the task as loaded.
+        let mut base = list.to_vec();
+        let mut seen = HashSet::new();
+        for entry in &entries {
+            if !seen.insert(entry.id.as_str()) {
+                continue;
+            }
+            match (entry.op.as_str(), &entry.before) {
+                ("add", _) => base.retain(|todo| todo.id != entry.id),
+                (_, Some(before)) => match base.iter_mut().find(|todo| todo.id == entry.id) {
+                    Some(todo) => *todo = before.clone(),
+                    None => base.push(before.clone()),
+                },
+                (_, None) => {},
+            }
+        }
+
+        let mut ours = base.clone();
+        for entry in entries {
+            let existing = ours.iter_mut().find(|existing| existing.id == entry.id);
+            match (entry.op.as_str(), entry.todo, existing) {
+                ("delete", _, _) => ours.retain(|todo| todo.id != entry.id),
+                (_, Some(todo), Some(existing)) => *existing = todo,
+                ("add", Some(todo), None) => ours.push(todo),
+                (op, Some(todo), None) => {
+                    replayed.conflicts.push(format!("\"{}\" was removed from the list, dropped the {}", todo.description, op));
+                },
+                (_, None, _) => {},
+            }
+        }
+
+        if base == list {
+            return Ok((ours, replayed));
+        }
+        let (merged, conflicts) = merge(&base, &ours, list);
+        replayed.conflicts.extend(conflicts);
+        Ok((merged, replayed))
+    }
+
+    // Called once the list itself has been saved with every entry applied
+    pub fn compact(&mut self) -> Result<(), TodoError> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if !contents.is_empty() {
+            let mut archive = OpenOptions::new().create(true).append(true).open(&self.archive)?;
+            archive.write_all(&contents)?;
+            archive.sync_data()?;
+        }
+        File::create(&self.path)?.sync_all()?;
+        self.entries = 0;
+        Ok(())
+    }
+
+    // Drops the journal along with its archive
+    pub fn remove(&mut self) -> Result<(), TodoError> {
+        for path in [&self.path, &self.archive] {
+            match fs::remove_file(path) {
+                Ok(()) => {},
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.entries = 0;
+        Ok(())
+    }
+}
+
+fn line(operation: &Operation, before: Option<&Todo>) -> String {
+    let (id, payload) = match operation {
+        Operation::Add(todo) | Operation::Toggle(todo) | Operation::Edit(todo) => (todo.id.as_str(), escape(&todo.to_string())),
+        Operation::Delete(id) => (id.as_str(), String::new()),
+    };
+    format!(
+        "{}\t{}\t{}\t{}\t{}\n",
+        Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+        operation.name(),
+        id,
+        payload,
+        before.map(|todo| escape(&todo.to_string())).unwrap_or_default(),
+    )
+}
+
+fn write_line(path: &Path, line: &str) -> Result<(), TodoError> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())?;
+    file.sync_data()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    fn task(id: &str, description: &str) -> Todo {
+        Todo { id: id.to_string(), ..Todo::parse(description) }
+    }
+
+    fn descriptions(todos: &[Todo]) -> Vec<&str> {
+        todos.iter().map(|todo| todo.description.as_str()).collect()
+    }
+
+    // A journal of its own, removed again when the test is done with it
+    fn journal(name: &str, test: impl FnOnce(&mut Journal)) {
+        let list = env::temp_dir().join(format!("todo-rs-{}-{}.txt", process::id(), name));
+        let mut journal = Journal::new(&list);
+        test(&mut journal);
+        journal.remove().unwrap();
+    }
+
+    #[test]
+    fn replaying_is_idempotent() {
+        journal("idempotent", |journal| {
+            let list = [task("a", "Call mom")];
+            journal.append(&Operation::Edit(task("a", "Call mom tonight")), Some(&list[0])).unwrap();
+            journal.append(&Operation::Add(task("b", "Feed the cat")), None).unwrap();
+
+            let (once, replayed) = journal.replay(&list).unwrap();
+            assert_eq!(descriptions(&once), ["Call mom tonight", "Feed the cat"]);
+            assert!(replayed.conflicts.is_empty());
+            assert_eq!(journal.len(), 2);
+            // As if the list had been saved with the entries but the journal
+            // not emptied yet
+            let (twice, replayed) = journal.replay(&once).unwrap();
+            assert_eq!(descriptions(&twice), ["Call mom tonight", "Feed the cat"]);
+            assert!(replayed.conflicts.is_empty());
+        });
+    }
+
+    #[test]
+    fn deleted_tasks_stay_deleted_after_a_reload() {
+        journal("delete", |journal| {
+            let list = [task("a", "Call mom"), task("b", "Feed the cat")];
+            journal.append(&Operation::Delete(String::from("b")), Some(&list[1])).unwrap();
+            let (todos, _) = journal.replay(&list).unwrap();
+            assert_eq!(descriptions(&todos), ["Call mom"]);
+            // The list was saved without the task, the entry is still there
+            let (todos, replayed) = journal.replay(&todos).unwrap();
+            assert_eq!(descriptions(&todos), ["Call mom"]);
+            assert!(replayed.conflicts.is_empty());
+        });
+    }
+
+    #[test]
+    fn outside_edits_are_merged_with_the_entries() {
+        journal("merge", |journal| {
+            let saved = [task("a", "Call mom"), task("b", "Feed the cat")];
+            journal.append(&Operation::Edit(task("a", "Call mom tonight")), Some(&saved[0])).unwrap();
+            // Another program edited both tasks since
+            let on_disk = [task("a", "Call mom tomorrow"), task("b", "Feed the dog")];
+            let (todos, replayed) = journal.replay(&on_disk).unwrap();
+            assert_eq!(descriptions(&todos), ["Call mom tonight", "Feed the dog"]);
+            assert_eq!(replayed.conflicts.len(), 1);
+        });
+    }
+
+    #[test]
+    fn malformed_entries_are_reported() {
+        journal("malformed", |journal| {
+            fs::write(&journal.path, "2023-05-01T10:00:00Z\tfrobnicate\ta\tCall mom\t\nnot an entry\n").unwrap();
+            let (todos, replayed) = journal.replay(&[task("a", "Call mom")]).unwrap();
+            assert_eq!(descriptions(&todos), ["Call mom"]);
+            assert_eq!(replayed.malformed.len(), 2);
+        });
+    }
+}
@@ -0,0 +1,275 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::TodoError;
+use crate::lock::FileLock;
+
+// File extensions of the storage backends. Sidecar files such as
+// `work.txt.journal`, `work.txt.bak.1` or `work.db-wal` don't end in one
+// of these, so they never show up as lists of their own.
+const EXTENSIONS: [&str; 7] = ["txt", "json", "db", "sqlite", "sqlite3", "org", "taskpaper"];
+
+pub const DEFAULT_LIST: &str = "todo";
+// Files kept alongside each list, see `archive_path` and `trash_path`
+const ARCHIVE: &str = "done.txt";
+const TRASH: &str = "trash.txt";
+
+// A directory holding one file per named list, e.g. `work.txt`,
+// `home.json` and `shopping.db`, or a single list file. Other files next to
+// a single list aren't lists, whatever their extension.
+pub struct Lists {
+    dir: PathBuf,
+    single: Option<PathBuf>,
+}
+
+impl Lists {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            single: None,
+        }
+    }
+
+    // `location` is either a directory of lists or one list file, which is
+    // then the only list. Returns the lists and the one to open, creating
+    // whatever is missing.
+    pub fn locate(location: &Path) -> Result<(Self, PathBuf), TodoError> {
+        let lists = if location.is_dir() || location.extension().is_none() {
+            Lists::new(location)
+        } else {
+            let dir = location.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            Self {
+                dir: dir.to_path_buf(),
+                single: Some(location.to_path_buf()),
+            }
+        };
+        fs::create_dir_all(&lists.dir)?;
+        let path = lists.default_list()?;
+        // Start on an empty list rather than failing on the first save
+        if !path.exists() {
+            fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+        }
+        Ok((lists, path))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Names and paths of every list, sorted by name
+    pub fn all(&self) -> Result<Vec<(String, PathBuf)>, TodoError> {
+        if let Some(path) = &self.single {
+            return Ok(vec![(self.name_of(path), path.clone())]);
+        }
+        let mut lists = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if !path.is_file() || !is_list_file(&path) {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                lists.push((name.to_string(), path.clone()));
+            }
+        }
+        lists.sort();
+        Ok(lists)
+    }
+
+    pub fn find(&self, name: &str) -> Result<PathBuf, TodoError> {
+        self.all()?
+            .into_iter()
+            .find(|(list, _)| list == name)
+            .map(|(_, path)| path)
+            .ok_or_else(|| TodoError::NotFound(format!("list \"{}\"", name)))
+    }
+
+    pub fn name_of(&self, path: &Path) -> String {
+        stem(path).to_string()
+    }
+
+    // The list to open when none was asked for
+    pub fn default_list(&self) -> Result<PathBuf, TodoError> {
+        if let Some(path) = &self.single {
+            return Ok(path.clone());
+        }
+        if let Ok(path) = self.find(DEFAULT_LIST) {
+            return Ok(path);
+        }
+        match self.all()?.into_iter().next() {
+            Some((_, path)) => Ok(path),
+            None => Ok(self.dir.join(format!("{}.txt", DEFAULT_LIST))),
+        }
+    }
+
+    // The new list is a todo.txt file unless the name has an extension
+    pub fn create(&self, name: &str) -> Result<PathBuf, TodoError> {
+        self.check_directory()?;
+        let path = self.path_for(name)?;
+        if self.find(stem(&path)).is_ok() {
+            return Err(already_exists(stem(&path)));
+        }
+        fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+        Ok(path)
+    }
+
+    // Renames the list along with its archive, trash, journal, backups and lock
+    // files
+    pub fn rename(&self, path: &Path, name: &str) -> Result<PathBuf, TodoError> {
+        self.check_directory()?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("txt");
+        let new_path = self.path_for(name)?.with_extension(extension);
+        if self.find(stem(&new_path)).is_ok() {
+            return Err(already_exists(stem(&new_path)));
+        }
+        let _instance = lock_for_change(path)?;
+        for (companion, new_companion) in companions(path).into_iter().zip(companions(&new_path)) {
+            if companion.exists() {
+                rename_with_sidecars(&companion, &new_companion)?;
+            }
+        }
+        rename_with_sidecars(path, &new_path)?;
+        Ok(new_path)
+    }
+
+    // Deletes the list along with its archive, trash, journal, backups and lock
+    // files
+    pub fn delete(&self, path: &Path) -> Result<(), TodoError> {
+        self.check_directory()?;
+        let _instance = lock_for_change(path)?;
+        for companion in companions(path) {
+            if companion.exists() {
+                remove_with_sidecars(&companion)?;
+            }
+        }
+        remove_with_sidecars(path)
+    }
+
+    // Lists are only created, renamed or deleted in a directory of lists
+    fn check_directory(&self) -> Result<(), TodoError> {
+        match &self.single {
+            Some(path) => Err(TodoError::Unsupported(format!(
+                "{} was opened as a single list, open its directory to manage lists",
+                path.display(),
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn path_for(&self, name: &str) -> Result<PathBuf, TodoError> {
+        let name = name.trim();
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(TodoError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("\"{}\" isn't a valid list name", name),
+            )));
+        }
+        let path = self.dir.join(name);
+        let path = match is_list_file(&path) || is_companion_file(&path) {
+            true => path,
+            false => self.dir.join(format!("{}.txt", name)),
+        };
+        if is_companion_file(&path) {
+            return Err(TodoError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("\"{}\" is reserved for archived and deleted tasks", name),
+            )));
+        }
+        Ok(path)
+    }
+}
+
+// Where the tasks archived from a list go: `done.txt` next to `todo.txt`,
+// as todo.txt tools expect, and `<name>.done.txt` for other lists
+pub fn archive_path(path: &Path) -> PathBuf {
+    companion_path(path, ARCHIVE)
+}
+
+// Where deleted tasks wait to be restored or purged: `trash.txt`, or
+// `<name>.trash.txt`
+pub fn trash_path(path: &Path) -> PathBuf {
+    companion_path(path, TRASH)
+}
+
+fn companion_path(path: &Path, file: &str) -> PathBuf {
+    let name = match stem(path) {
+        DEFAULT_LIST => String::from(file),
+        stem => format!("{}.{}", stem, file),
+    };
+    path.with_file_name(name)
+}
+
+fn companions(path: &Path) -> [PathBuf; 2] {
+    [archive_path(path), trash_path(path)]
+}
+
+fn is_companion_file(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    [ARCHIVE, TRASH].iter().any(|file| name == *file || name.ends_with(&format!(".{}", file)))
+}
+
+fn is_list_file(path: &Path) -> bool {
+    !is_companion_file(path)
+        && path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+fn stem(path: &Path) -> &str {
+    path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default()
+}
+
+fn already_exists(name: &str) -> TodoError {
+    TodoError::Io(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("list \"{}\" already exists", name),
+    ))
+}
+
+// Another instance working on the list would keep writing to the old files
+fn lock_for_change(path: &Path) -> Result<FileLock, TodoError> {
+    FileLock::instance(path)?.ok_or_else(|| TodoError::ReadOnly(path.to_path_buf()))
+}
+
+fn rename_with_sidecars(from: &Path, to: &Path) -> Result<(), TodoError> {
+    for (sidecar, suffix) in sidecars(from)? {
+        let mut new_sidecar = to.to_path_buf().into_os_string();
+        new_sidecar.push(&suffix);
+        fs::rename(sidecar, new_sidecar)?;
+    }
+    fs::rename(from, to)?;
+    Ok(())
+}
+
+fn remove_with_sidecars(path: &Path) -> Result<(), TodoError> {
+    for (sidecar, _) in sidecars(path)? {
+        fs::remove_file(sidecar)?;
+    }
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+// Files named `<list file><suffix>`, such as `work.txt.journal`, with the suffix
+fn sidecars(path: &Path) -> Result<Vec<(PathBuf, String)>, TodoError> {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(vec![]);
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut sidecars = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(suffix) = file_name.to_str().and_then(|f| f.strip_prefix(name)) else {
+            continue;
+        };
+        if suffix.starts_with('.') || suffix.starts_with('-') {
+            sidecars.push((entry.path(), suffix.to_string()));
+        }
+    }
+    Ok(sidecars)
+}
@@ -0,0 +1,58 @@
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use crate::storage::with_suffix;
+
+// Advisory flock(2) on a sidecar file next to the list. The list itself
+// can't be locked since every save replaces it with a new file.
+//
+// `<file>.lock` is held for the duration of a single load (shared) or
+// save (exclusive). Scripts can take part with e.g.
+// `flock todo.txt.lock sh -c '...'`.
+//
+// `<file>.instance.lock` is held by the TUI for as long as it runs, a
+// second instance that can't get it opens the list read-only.
+pub struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    pub fn shared(path: &Path) -> io::Result<Self> {
+        let file = open_lock_file(&with_suffix(path, ".lock"))?;
+        file.lock_shared()?;
+        Ok(Self { file })
+    }
+
+    pub fn exclusive(path: &Path) -> io::Result<Self> {
+        let file = open_lock_file(&with_suffix(path, ".lock"))?;
+        file.lock_exclusive()?;
+        Ok(Self { file })
+    }
+
+    // Returns `None` when another instance already has the list open
+    pub fn instance(path: &Path) -> io::Result<Option<Self>> {
+        let file = open_lock_file(&with_suffix(path, ".instance.lock"))?;
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(Some(Self { file })),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+fn open_lock_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
@@ -1,10 +1,50 @@
 mod app;
+mod cli;
+mod commands;
+mod config;
+mod dates;
+mod error;
+mod formats;
+mod history;
+mod journal;
+mod lists;
+mod lock;
+mod merge;
+mod storage;
 mod todo;
 mod ui;
+mod watcher;
 
+// Runs a subcommand to completion, or returns the app to start
+fn start(cli: cli::Cli) -> Result<Option<app::App>, error::TodoError> {
+    let config = config::Config::load()?;
+    let location = config::data_location(cli.file, &config)?;
+    match cli.command {
+        Some(command) => commands::run(command, &config, &location).map(|()| None),
+        None => app::App::new(config, &location).map(Some),
+    }
+}
 
 fn main() {
-    let mut app = app::App::new();
+    let cli = match cli::Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if cli.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+    let mut app = match start(cli) {
+        Ok(Some(app)) => app,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
     if let Err(e) = app.run() {
         eprintln!("Error: {}", e);
         std::process::exit(1);
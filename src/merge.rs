@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use crate::todo::Todo;
+
+// Three-way merge of a list: `base` is the state last read from or written
+// to disk, `ours` the state in memory and `theirs` what is on disk now.
+// Tasks are matched by id. Changes made on only one side are applied. When
+// both sides changed the same task differently, the edit is kept over a
+// deletion and our version over theirs, and the task is reported as a
+// conflict.
+pub fn merge(base: &[Todo], ours: &[Todo], theirs: &[Todo]) -> (Vec<Todo>, Vec<String>) {
+    let base = by_id(base);
+    let ours_by_id = by_id(ours);
+    let theirs_by_id = by_id(theirs);
+
+    let mut merged = vec![];
+    let mut conflicts = vec![];
+    let mut resolve = |b: Option<&Todo>, o: Option<&Todo>, t: Option<&Todo>| -> Option<Todo> {
+        let picked = if o == b {
+            t
+        } else if t == b || o == t {
+            o
+        } else {
+            match (o, t) {
+                (Some(o), _) => {
+                    conflicts.push(format!("\"{}\" changed on disk too, kept the local version", o.description));
+                    Some(o)
+                },
+                (None, Some(t)) => {
+                    conflicts.push(format!("\"{}\" was changed on disk, kept it instead of deleting it", t.description));
+                    Some(t)
+                },
+                (None, None) => None,
+            }
+        };
+        picked.cloned()
+    };
+
+    // Keep the order of the file on disk and append tasks only we know about
+    for todo in theirs {
+        let id = todo.id.as_str();
+        if let Some(todo) = resolve(base.get(id).copied(), ours_by_id.get(id).copied(), Some(todo)) {
+            merged.push(todo);
+        }
+    }
+    for todo in ours {
+        let id = todo.id.as_str();
+        if theirs_by_id.contains_key(id) {
+            continue;
+        }
+        if let Some(todo) = resolve(base.get(id).copied(), Some(todo), None) {
+            merged.push(todo);
+        }
+    }
+
+    (merged, conflicts)
+}
+
+fn by_id(todos: &[Todo]) -> HashMap<&str, &Todo> {
+    todos.iter().map(|todo| (todo.id.as_str(), todo)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(id: &str, description: &str) -> Todo {
+        Todo { id: id.to_string(), ..Todo::parse(description) }
+    }
+
+    fn descriptions(todos: &[Todo]) -> Vec<&str> {
+        todos.iter().map(|todo| todo.description.as_str()).collect()
+    }
+
+    #[test]
+    fn edits_on_one_side_are_applied() {
+        let base = [task("a", "Call mom"), task("b", "Feed the cat")];
+        let ours = [task("a", "Call mom tonight"), task("b", "Feed the cat")];
+        let theirs = [task("a", "Call mom"), task("b", "Feed the dog")];
+        let (merged, conflicts) = merge(&base, &ours, &theirs);
+        assert_eq!(descriptions(&merged), ["Call mom tonight", "Feed the dog"]);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn our_edit_wins_when_both_sides_changed_a_task() {
+        let base = [task("a", "Call mom")];
+        let (merged, conflicts) = merge(&base, &[task("a", "Call mom tonight")], &[task("a", "Call mom tomorrow")]);
+        assert_eq!(descriptions(&merged), ["Call mom tonight"]);
+        assert_eq!(conflicts.len(), 1);
+
+        // The same edit on both sides isn't a conflict
+        let (merged, conflicts) = merge(&base, &[task("a", "Call dad")], &[task("a", "Call dad")]);
+        assert_eq!(descriptions(&merged), ["Call dad"]);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn an_edit_is_kept_over_a_deletion() {
+        let base = [task("a", "Call mom"), task("b", "Feed the cat")];
+        // We deleted what they edited
+        let (merged, conflicts) = merge(&base, &[task("b", "Feed the cat")], &[task("a", "Call mom tonight"), task("b", "Feed the cat")]);
+        assert_eq!(descriptions(&merged), ["Call mom tonight", "Feed the cat"]);
+        assert_eq!(conflicts.len(), 1);
+        // They deleted what we edited
+        let (merged, conflicts) = merge(&base, &[task("a", "Call mom"), task("b", "Feed the dog")], &[task("a", "Call mom")]);
+        assert_eq!(descriptions(&merged), ["Call mom", "Feed the dog"]);
+        assert_eq!(conflicts.len(), 1);
+        // Deletions of unchanged tasks just happen
+        let (merged, conflicts) = merge(&base, &[task("a", "Call mom")], &[task("b", "Feed the cat")]);
+        assert!(merged.is_empty());
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn tasks_added_on_both_sides_are_all_kept() {
+        let base = [task("a", "Call mom")];
+        let ours = [task("a", "Call mom"), task("b", "Ours")];
+        let theirs = [task("c", "Theirs"), task("a", "Call mom")];
+        let (merged, conflicts) = merge(&base, &ours, &theirs);
+        assert_eq!(descriptions(&merged), ["Theirs", "Call mom", "Ours"]);
+        assert!(conflicts.is_empty());
+    }
+}
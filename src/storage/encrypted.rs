@@ -0,0 +1,229 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use zeroize::Zeroizing;
+
+use super::text;
+use super::{write_atomically, Loaded, Storage};
+use crate::error::TodoError;
+use crate::todo::Todo;
+
+// An encrypted list is the todo.txt format sealed with ChaCha20-Poly1305,
+// keyed with Argon2id from a passphrase:
+//
+//   MAGIC | salt (16 bytes) | nonce (12 bytes) | ciphertext and tag
+//
+// The salt only changes when the list is re-keyed, a new nonce is drawn for
+// every save.
+const MAGIC: &[u8] = b"todo-rs encrypted 1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+pub struct EncryptedStorage {
+    path: PathBuf,
+    backups: usize,
+    passphrase: Option<Zeroizing<String>>,
+    // Key derived for the salt of the last file read or written, deriving
+    // one is deliberately slow
+    key: Option<([u8; SALT_LEN], Zeroizing<[u8; KEY_LEN]>)>,
+}
+
+impl EncryptedStorage {
+    // Opens an existing encrypted list, `unlock` must be called before it
+    // can be read
+    pub fn new(path: &Path, backups: usize) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            backups,
+            passphrase: None,
+            key: None,
+        }
+    }
+
+    // A list that is encrypted from its next save on, under a fresh salt
+    pub fn with_passphrase(path: &Path, backups: usize, passphrase: &str) -> Self {
+        Self {
+            passphrase: Some(Zeroizing::new(passphrase.to_string())),
+            ..Self::new(path, backups)
+        }
+    }
+
+    fn key_for(&mut self, salt: [u8; SALT_LEN]) -> Result<Zeroizing<[u8; KEY_LEN]>, TodoError> {
+        if let Some((cached, key)) = &self.key {
+            if *cached == salt {
+                return Ok(key.clone());
+            }
+        }
+        let passphrase = self.passphrase.as_ref().ok_or_else(|| TodoError::Locked(self.path.clone()))?;
+        let mut key = Zeroizing::new([0; KEY_LEN]);
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key[..])
+            .map_err(|e| TodoError::Unsupported(format!("can't derive a key: {}", e)))?;
+        self.key = Some((salt, key.clone()));
+        Ok(key)
+    }
+
+    fn decrypt(&mut self, contents: &[u8]) -> Result<Loaded, TodoError> {
+        let (salt, nonce, ciphertext) = split(contents).ok_or_else(|| TodoError::Parse {
+            line: 1,
+            message: format!("{} is not an encrypted todo-rs list", self.path.display()),
+        })?;
+        let key = self.key_for(salt)?;
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key[..]))
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map(Zeroizing::new)
+            .map_err(|_| TodoError::Decrypt(self.path.clone()))?;
+        text::parse(&plaintext)
+    }
+}
+
+impl Storage for EncryptedStorage {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&mut self) -> Result<Loaded, TodoError> {
+        match fs::read(&self.path) {
+            Ok(contents) => self.load_bytes(&contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Loaded { todos: vec![], malformed: vec![] }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&mut self, todos: &[Todo]) -> Result<(), TodoError> {
+        let salt = match &self.key {
+            Some((salt, _)) => *salt,
+            None => {
+                let mut salt = [0; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                salt
+            },
+        };
+        let key = self.key_for(salt)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plaintext = Zeroizing::new(text::serialize(todos));
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key[..]))
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| TodoError::Unsupported(format!("can't encrypt {}", self.path.display())))?;
+
+        let mut contents = MAGIC.to_vec();
+        contents.extend_from_slice(&salt);
+        contents.extend_from_slice(&nonce);
+        contents.extend_from_slice(&ciphertext);
+        write_atomically(&self.path, &contents, self.backups)?;
+        Ok(())
+    }
+
+    fn load_bytes(&mut self, contents: &[u8]) -> Result<Loaded, TodoError> {
+        match contents.is_empty() {
+            true => Ok(Loaded { todos: vec![], malformed: vec![] }),
+            false => self.decrypt(contents),
+        }
+    }
+
+    // Checks the passphrase against the file so a typo is caught right away
+    fn unlock(&mut self, passphrase: &str) -> Result<(), TodoError> {
+        self.passphrase = Some(Zeroizing::new(passphrase.to_string()));
+        self.key = None;
+        if let Err(e) = self.load() {
+            self.passphrase = None;
+            self.key = None;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn is_encrypted(&self) -> bool {
+        true
+    }
+
+    fn companion(&self, path: &Path, backups: usize) -> Box<dyn Storage> {
+        Box::new(Self {
+            passphrase: self.passphrase.clone(),
+            ..Self::new(path, backups)
+        })
+    }
+}
+
+fn split(contents: &[u8]) -> Option<([u8; SALT_LEN], &[u8], &[u8])> {
+    let rest = contents.strip_prefix(MAGIC)?;
+    if rest.len() < SALT_LEN + NONCE_LEN {
+        return None;
+    }
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    Some((salt.try_into().ok()?, nonce, ciphertext))
+}
+
+pub fn is_encrypted_file(path: &Path) -> bool {
+    let mut magic = [0; MAGIC.len()];
+    File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && magic == MAGIC
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    // An encrypted list under `passphrase` with a single task, removed again
+    // when the test is done with it
+    fn list(name: &str, passphrase: &str, test: impl FnOnce(&Path)) {
+        let path = env::temp_dir().join(format!("todo-rs-{}-{}.txt", process::id(), name));
+        let todo = Todo { id: String::from("a"), notes: String::from("secret"), ..Todo::parse("(A) Call mom") };
+        EncryptedStorage::with_passphrase(&path, 0, passphrase).save(&[todo]).unwrap();
+        test(&path);
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn open(path: &Path, passphrase: &str) -> Result<Loaded, TodoError> {
+        let mut storage = EncryptedStorage::new(path, 0);
+        storage.unlock(passphrase)?;
+        storage.load()
+    }
+
+    #[test]
+    fn lists_read_back_with_their_passphrase() {
+        list("round-trip", "hunter2", |path| {
+            let contents = fs::read(path).unwrap();
+            assert!(is_encrypted_file(path));
+            assert!(!contents.windows(6).any(|window| window == b"secret"));
+            let loaded = open(path, "hunter2").unwrap();
+            assert_eq!(loaded.todos[0].description, "Call mom");
+            assert_eq!(loaded.todos[0].priority, Some('A'));
+            assert_eq!(loaded.todos[0].notes, "secret");
+        });
+    }
+
+    #[test]
+    fn wrong_passphrases_and_tampering_are_caught() {
+        list("tampered", "hunter2", |path| {
+            assert!(matches!(open(path, "hunter3"), Err(TodoError::Decrypt(_))));
+            let mut contents = fs::read(path).unwrap();
+            let last = contents.len() - 1;
+            contents[last] ^= 1;
+            fs::write(path, &contents).unwrap();
+            assert!(matches!(open(path, "hunter2"), Err(TodoError::Decrypt(_))));
+        });
+    }
+
+    #[test]
+    fn rekeying_draws_a_new_salt() {
+        list("rekey", "hunter2", |path| {
+            let salt = |path: &Path| split(&fs::read(path).unwrap()).unwrap().0;
+            let before = salt(path);
+            let todos = open(path, "hunter2").unwrap().todos;
+            EncryptedStorage::with_passphrase(path, 0, "correct horse").save(&todos).unwrap();
+            assert_ne!(salt(path), before);
+            assert!(matches!(open(path, "hunter2"), Err(TodoError::Decrypt(_))));
+            assert_eq!(open(path, "correct horse").unwrap().todos[0].description, "Call mom");
+        });
+    }
+}
@@ -0,0 +1,124 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{write_atomically, Loaded, Storage};
+use crate::error::TodoError;
+use crate::todo::Todo;
+
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct JsonFile {
+    version: u32,
+    todos: Vec<JsonTodo>,
+}
+
+// Only the fields written to todo.txt are stored, projects, contexts and
+// tags are parsed back out of the description.
+#[derive(Serialize, Deserialize)]
+struct JsonTodo {
+    #[serde(default)]
+    id: String,
+    description: String,
+    done: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    priority: Option<char>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    creation_date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    completion_date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    notes: String,
+}
+
+impl From<&Todo> for JsonTodo {
+    fn from(todo: &Todo) -> Self {
+        Self {
+            id: todo.id.clone(),
+            description: todo.description.clone(),
+            done: todo.done,
+            priority: todo.priority,
+            creation_date: todo.creation_date,
+            completion_date: todo.completion_date,
+            notes: todo.notes.clone(),
+        }
+    }
+}
+
+impl From<JsonTodo> for Todo {
+    fn from(json: JsonTodo) -> Self {
+        let mut todo = Todo {
+            id: json.id,
+            done: json.done,
+            priority: json.priority,
+            creation_date: json.creation_date,
+            completion_date: json.completion_date,
+            notes: json.notes,
+            ..Default::default()
+        };
+        todo.set_description(&json.description);
+        todo
+    }
+}
+
+pub struct JsonStorage {
+    path: PathBuf,
+    backups: usize,
+}
+
+impl JsonStorage {
+    pub fn new(path: &Path, backups: usize) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            backups,
+        }
+    }
+}
+
+impl Storage for JsonStorage {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&mut self) -> Result<Loaded, TodoError> {
+        match fs::read(&self.path) {
+            // A freshly created list file is still empty
+            Ok(contents) if contents.iter().all(u8::is_ascii_whitespace) => Ok(Loaded { todos: vec![], malformed: vec![] }),
+            Ok(contents) => parse(&contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Loaded { todos: vec![], malformed: vec![] }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&mut self, todos: &[Todo]) -> Result<(), TodoError> {
+        let file = JsonFile {
+            version: FORMAT_VERSION,
+            todos: todos.iter().map(JsonTodo::from).collect(),
+        };
+        let mut contents = serde_json::to_vec_pretty(&file)?;
+        contents.push(b'\n');
+        write_atomically(&self.path, &contents, self.backups)?;
+        Ok(())
+    }
+
+    fn load_bytes(&mut self, contents: &[u8]) -> Result<Loaded, TodoError> {
+        parse(contents)
+    }
+}
+
+fn parse(contents: &[u8]) -> Result<Loaded, TodoError> {
+    let file: JsonFile = serde_json::from_slice(contents)?;
+    if file.version != FORMAT_VERSION {
+        return Err(TodoError::Parse {
+            line: 1,
+            message: format!("unsupported format version {}", file.version),
+        });
+    }
+    Ok(Loaded {
+        todos: file.todos.into_iter().map(Todo::from).collect(),
+        malformed: vec![],
+    })
+}
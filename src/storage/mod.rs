@@ -0,0 +1,203 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::TodoError;
+use crate::todo::Todo;
+
+mod encrypted;
+mod json;
+mod org;
+mod outline;
+mod sqlite;
+mod taskpaper;
+mod text;
+
+pub use encrypted::{is_encrypted_file, EncryptedStorage};
+pub use json::JsonStorage;
+pub use org::OrgStorage;
+pub use sqlite::SqliteStorage;
+pub use taskpaper::TaskPaperStorage;
+pub use text::{escape, unescape, TextStorage};
+
+pub const DEFAULT_BACKUPS: usize = 3;
+
+pub struct Loaded {
+    pub todos: Vec<Todo>,
+    // `TodoError::Parse` for each line that couldn't be read cleanly. The
+    // task is still kept, parsed as well as possible, so that saving the list
+    // never drops data.
+    pub malformed: Vec<TodoError>,
+}
+
+// Where a `Todos` list is persisted. Backends always read and write the
+// whole list; a missing file loads as an empty list.
+pub trait Storage {
+    fn path(&self) -> &Path;
+
+    fn load(&mut self) -> Result<Loaded, TodoError>;
+
+    fn save(&mut self, todos: &[Todo]) -> Result<(), TodoError>;
+
+    // Reads a list from what was once the contents of its file, such as a
+    // backup or an older version
+    fn load_bytes(&mut self, contents: &[u8]) -> Result<Loaded, TodoError>;
+
+    // Reads the n-th rotating backup, 1 being the newest
+    fn load_backup(&mut self, n: usize) -> Result<Loaded, TodoError> {
+        let contents = read_backup(self.path(), n)?;
+        self.load_bytes(&contents)
+    }
+
+    // Encrypted backends need the passphrase before they can load or save
+    fn unlock(&mut self, _passphrase: &str) -> Result<(), TodoError> {
+        Ok(())
+    }
+
+    fn is_encrypted(&self) -> bool {
+        false
+    }
+
+    // Rewrites a task the way it will read back, for formats that can't keep
+    // every description as typed
+    fn normalize(&self, _todo: &mut Todo) {}
+
+    // Whether the last load gave tasks ids that aren't in the file yet
+    fn made_up_ids(&self) -> bool {
+        false
+    }
+
+    // Storage for a file kept alongside the list, such as its archive.
+    // These are todo.txt files, encrypted when the list itself is.
+    fn companion(&self, path: &Path, backups: usize) -> Box<dyn Storage> {
+        Box::new(TextStorage::new(path, backups))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Text,
+    Json,
+    Sqlite,
+    Org,
+    TaskPaper,
+    Encrypted,
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" | "txt" | "todo.txt" => Some(Backend::Text),
+            "json" => Some(Backend::Json),
+            "sqlite" | "sqlite3" | "db" => Some(Backend::Sqlite),
+            "org" => Some(Backend::Org),
+            "taskpaper" => Some(Backend::TaskPaper),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match extension.to_ascii_lowercase().as_str() {
+            "json" => Backend::Json,
+            "db" | "sqlite" | "sqlite3" => Backend::Sqlite,
+            "org" => Backend::Org,
+            "taskpaper" => Backend::TaskPaper,
+            _ => Backend::Text,
+        }
+    }
+
+    // Encrypted files are recognised by their header whatever their name,
+    // other lists use the configured backend or their extension
+    pub fn detect(path: &Path, configured: Option<Backend>) -> Self {
+        if is_encrypted_file(path) {
+            return Backend::Encrypted;
+        }
+        configured.unwrap_or_else(|| Backend::from_path(path))
+    }
+}
+
+pub fn open(path: &Path, backend: Backend, backups: usize) -> Box<dyn Storage> {
+    match backend {
+        Backend::Text => Box::new(TextStorage::new(path, backups)),
+        Backend::Json => Box::new(JsonStorage::new(path, backups)),
+        Backend::Sqlite => Box::new(SqliteStorage::new(path, backups)),
+        Backend::Org => Box::new(OrgStorage::new(path, backups)),
+        Backend::TaskPaper => Box::new(TaskPaperStorage::new(path, backups)),
+        Backend::Encrypted => Box::new(EncryptedStorage::new(path, backups)),
+    }
+}
+
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// Writes `contents` next to `path`, syncs it to disk and renames it over
+// `path`, so a crash leaves either the old or the new file but never half of
+// one. Before replacing, the current file is rotated into `path.bak.1` ..
+// `path.bak.<backups>`.
+pub fn write_atomically(path: &Path, contents: &[u8], backups: usize) -> io::Result<()> {
+    let tmp = with_suffix(path, ".tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    if backups > 0 && path.exists() {
+        rotate_backups(path, backups)?;
+        fs::copy(path, backup_path(path, 1))?;
+    }
+
+    fs::rename(&tmp, path)?;
+    // Make the rename itself durable
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// Moves `path.bak.1` .. `path.bak.<backups - 1>` one place up, dropping the
+// oldest, so that the current file can become `path.bak.1`
+pub(super) fn rotate_backups(path: &Path, backups: usize) -> io::Result<()> {
+    for n in (1..backups).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, n + 1))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    with_suffix(path, &format!(".bak.{}", n))
+}
+
+pub fn remove_backups(path: &Path) -> io::Result<()> {
+    for n in 1.. {
+        match fs::remove_file(backup_path(path, n)) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+pub fn read_backup(path: &Path, n: usize) -> Result<Vec<u8>, TodoError> {
+    let backup = backup_path(path, n);
+    fs::read(&backup).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => TodoError::NotFound(format!("backup {}", backup.display())),
+        _ => TodoError::Io(e),
+    })
+}
@@ -0,0 +1,292 @@
+use chrono::NaiveDate;
+
+use super::outline::{read_notes, Block, Fields, Outline, OutlineStorage, TaskBlock};
+use crate::todo::{Todo, DATE_FORMAT};
+
+// Emacs Org files. Headlines with a TODO or DONE keyword are tasks:
+//   ** TODO [#A] Call mom :family:phone:
+//      SCHEDULED: <2023-05-02 Tue> DEADLINE: <2023-05-03 Wed>
+//      :PROPERTIES:
+//      :ID:       01H0ZKXJ6V4Q8Y1ZB1W0Q5N2X3
+//      :CREATED:  [2023-05-01 Mon]
+//      :END:
+//      Notes, the task's notes
+// Tags become `@contexts`, SCHEDULED a todo.txt `t:` date and DEADLINE a
+// `due:` date. Other keywords, such as NEXT or WAITING, aren't read as tasks.
+// A task headline under another task's is its subtask.
+pub type OrgStorage = OutlineStorage<Org>;
+
+pub struct Org;
+
+impl Outline for Org {
+    const TOP_LEVEL: &'static str = "*";
+
+    fn parse(contents: &str) -> Vec<Block> {
+        let lines = contents.split_inclusive('\n').collect::<Vec<_>>();
+        let mut blocks = vec![];
+        let mut text = String::new();
+        let mut i = 0;
+        while i < lines.len() {
+            if task_headline(lines[i]).is_none() {
+                text.push_str(lines[i]);
+                i += 1;
+                continue;
+            }
+            if !text.is_empty() {
+                blocks.push(Block::Text(std::mem::take(&mut text)));
+            }
+            // A task runs until the next headline, its subtasks are tasks
+            // of their own
+            let start = i;
+            i += 1;
+            while i < lines.len() && headline(lines[i]).is_none() {
+                i += 1;
+            }
+            blocks.push(Block::Task(Box::new(read_task(&lines[start..i]))));
+        }
+        if !text.is_empty() {
+            blocks.push(Block::Text(text));
+        }
+        blocks
+    }
+
+    fn render(todo: &Todo, stars: &str, block: Option<&TaskBlock>) -> Vec<String> {
+        let indent = Self::notes_indent(stars);
+        let fields = Fields::of(todo);
+
+        // Trailing contexts are the headline's tags
+        let mut words = fields.text.split(' ').filter(|word| !word.is_empty()).collect::<Vec<_>>();
+        let mut tags = vec![];
+        while let Some(tag) = words.last().and_then(|word| word.strip_prefix('@')).filter(|tag| is_tag(tag)) {
+            tags.insert(0, tag);
+            words.pop();
+        }
+        let title = words.join(" ");
+
+        let mut headline = format!("{} {}", stars, if todo.is_done() { "DONE" } else { "TODO" });
+        if let Some(priority) = fields.priority {
+            headline.push_str(&format!(" [#{}]", priority));
+        }
+        if !title.is_empty() {
+            headline.push(' ');
+            headline.push_str(&title);
+        }
+        if !tags.is_empty() {
+            headline.push_str(&format!(" :{}:", tags.join(":")));
+        }
+        headline.push('\n');
+
+        let mut planning = vec![];
+        if let (true, Some(date)) = (todo.is_done(), todo.completion_date) {
+            planning.push(format!("CLOSED: [{}]", timestamp(date)));
+        }
+        if let Some(date) = fields.start {
+            planning.push(format!("SCHEDULED: <{}>", timestamp(date)));
+        }
+        if let Some(date) = fields.due {
+            planning.push(format!("DEADLINE: <{}>", timestamp(date)));
+        }
+        let planning = match planning.is_empty() {
+            true => String::new(),
+            false => format!("{}{}\n", indent, planning.join(" ")),
+        };
+
+        let mut drawer = String::new();
+        let properties = block.map_or(&[][..], |block| block.properties.as_slice());
+        if !todo.id.is_empty() || todo.creation_date.is_some() || !properties.is_empty() {
+            drawer.push_str(&format!("{}:PROPERTIES:\n", indent));
+            if !todo.id.is_empty() {
+                drawer.push_str(&format!("{}:ID:       {}\n", indent, todo.id));
+            }
+            if let Some(date) = todo.creation_date {
+                drawer.push_str(&format!("{}:CREATED:  [{}]\n", indent, timestamp(date)));
+            }
+            for property in properties {
+                drawer.push_str(property);
+                drawer.push('\n');
+            }
+            drawer.push_str(&format!("{}:END:\n", indent));
+        }
+        vec![headline, planning, drawer]
+    }
+
+    fn notes_indent(stars: &str) -> String {
+        " ".repeat(stars.len() + 1)
+    }
+
+    fn deeper(stars: &str) -> String {
+        format!("{}*", stars)
+    }
+
+    fn depth(line: &str) -> Option<usize> {
+        headline(line).map(|(stars, _)| stars.len())
+    }
+}
+
+// The stars of a headline and what follows them
+fn headline(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let stars = line.len() - line.trim_start_matches('*').len();
+    if stars == 0 {
+        return None;
+    }
+    match line[stars..].strip_prefix(' ') {
+        Some(rest) => Some((&line[..stars], rest)),
+        None if line.len() == stars => Some((line, "")),
+        None => None,
+    }
+}
+
+// The stars, whether it's done and the rest of a TODO or DONE headline
+fn task_headline(line: &str) -> Option<(&str, bool, &str)> {
+    let (stars, rest) = headline(line)?;
+    let (keyword, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    match keyword {
+        "TODO" => Some((stars, false, rest)),
+        "DONE" => Some((stars, true, rest)),
+        _ => None,
+    }
+}
+
+fn read_task(lines: &[&str]) -> TaskBlock {
+    let (stars, done, rest) = task_headline(lines[0]).unwrap_or(("*", false, ""));
+    let mut rest = rest.trim();
+    let mut fields = Fields { text: String::new(), priority: None, start: None, due: None };
+    if let Some(cookie) = rest.strip_prefix("[#").and_then(|cookie| cookie.get(..2)).filter(|cookie| cookie.ends_with(']')) {
+        fields.priority = cookie.chars().next().filter(char::is_ascii_uppercase);
+        rest = rest[4..].trim_start();
+    }
+    let mut tags = vec![];
+    if let Some((title, last)) = rest.rsplit_once(' ').or(Some(("", rest))) {
+        if let Some(names) = last.strip_prefix(':').and_then(|last| last.strip_suffix(':')).filter(|names| names.split(':').all(is_tag)) {
+            tags = names.split(':').map(|tag| format!("@{}", tag)).collect();
+            rest = title.trim_end();
+        }
+    }
+    fields.text = std::iter::once(rest.to_string()).chain(tags).filter(|word| !word.is_empty()).collect::<Vec<_>>().join(" ");
+
+    // Planning comes right after the headline, then the property drawer
+    let mut next = 1;
+    let mut closed = None;
+    let mut parts = vec![lines[0].to_string(), String::new(), String::new()];
+    if let Some(line) = lines.get(next).filter(|line| is_planning(line)) {
+        closed = planning_date(line, "CLOSED:");
+        fields.start = planning_date(line, "SCHEDULED:");
+        fields.due = planning_date(line, "DEADLINE:");
+        parts[1] = line.to_string();
+        next += 1;
+    }
+    let (mut id, mut created, mut properties) = (String::new(), None, vec![]);
+    if lines.get(next).is_some_and(|line| line.trim() == ":PROPERTIES:") {
+        if let Some(end) = lines[next..].iter().position(|line| line.trim() == ":END:") {
+            for line in &lines[next + 1..next + end] {
+                let property = line.trim().strip_prefix(':').and_then(|property| property.split_once(':'));
+                match property {
+                    Some((key, value)) if key.eq_ignore_ascii_case("ID") => id = value.trim().to_string(),
+                    Some((key, value)) if key.eq_ignore_ascii_case("CREATED") => created = timestamp_date(value.trim()),
+                    _ => properties.push(line.trim_end_matches(['\n', '\r']).to_string()),
+                }
+            }
+            parts[2] = lines[next..next + end + 1].concat();
+            next += end + 1;
+        }
+    }
+
+    let notes = lines[next.min(lines.len())..].concat();
+    TaskBlock {
+        todo: fields.into_todo(id, done, created, closed, read_notes(&notes)),
+        text: lines.concat(),
+        indent: stars.to_string(),
+        parts,
+        properties,
+        notes,
+        new_id: None,
+    }
+}
+
+fn is_planning(line: &str) -> bool {
+    let line = line.trim_start();
+    ["CLOSED:", "SCHEDULED:", "DEADLINE:"].iter().any(|keyword| line.starts_with(keyword))
+}
+
+fn planning_date(line: &str, keyword: &str) -> Option<NaiveDate> {
+    let (_, after) = line.split_once(keyword)?;
+    timestamp_date(after.trim_start())
+}
+
+// `<2023-05-02 Tue>` or `[2023-05-02 Tue 10:00]`, repeaters and times are
+// left out
+fn timestamp_date(value: &str) -> Option<NaiveDate> {
+    let date = value.strip_prefix(['<', '['])?.get(..10)?;
+    NaiveDate::parse_from_str(date, DATE_FORMAT).ok()
+}
+
+fn timestamp(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d %a").to_string()
+}
+
+fn is_tag(tag: &str) -> bool {
+    !tag.is_empty() && tag.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '@' | '#' | '%'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::outline::tests::round_trip;
+
+    const FILE: &str = "\
+#+TITLE: Chores
+* Home
+** TODO [#A] Call mom :family:phone:
+   DEADLINE: <2023-05-03 Wed>
+   :PROPERTIES:
+   :ID:       call
+   :CREATED:  [2023-05-01 Mon]
+   :END:
+   Her new number is in the address book
+
+** DONE Feed the cat
+   CLOSED: [2023-05-02 Tue 08:00]
+   :PROPERTIES:
+   :ID:       feed
+   :END:
+** Someday
+Text under a heading
+* TODO Last line without a newline";
+
+    #[test]
+    fn an_unchanged_file_is_written_back_as_it_was() {
+        assert_eq!(round_trip::<Org>("unchanged.org", FILE, |_| {}), FILE);
+    }
+
+    #[test]
+    fn an_edited_task_keeps_what_surrounds_it() {
+        let saved = round_trip::<Org>("edited.org", FILE, |todos| {
+            let feed = todos.iter_mut().find(|todo| todo.id == "feed").unwrap();
+            feed.set_description("Feed the dog");
+        });
+        assert_eq!(saved, FILE.replace("Feed the cat", "Feed the dog"));
+    }
+
+    #[test]
+    fn priority_tags_and_deadline_are_read() {
+        let blocks = Org::parse(FILE);
+        let Some(Block::Task(call)) = blocks.iter().find(|block| matches!(block, Block::Task(task) if task.todo.id == "call")) else {
+            panic!("no task `call`");
+        };
+        let todo = &call.todo;
+        assert_eq!(todo.priority, Some('A'));
+        assert_eq!(todo.due_date(), NaiveDate::from_ymd_opt(2023, 5, 3));
+        assert!(todo.has_tag("@family") && todo.has_tag("@phone"));
+        assert_eq!(todo.notes, "Her new number is in the address book");
+    }
+
+    #[test]
+    fn priority_and_deadline_survive_an_edit() {
+        let saved = round_trip::<Org>("rendered.org", FILE, |todos| {
+            let call = todos.iter_mut().find(|todo| todo.id == "call").unwrap();
+            call.set_description("Call dad @family @phone due:2023-05-03");
+        });
+        assert!(saved.contains("** TODO [#A] Call dad :family:phone:\n   DEADLINE: <2023-05-03 Wed>\n"));
+    }
+}
@@ -0,0 +1,465 @@
+use chrono::NaiveDate;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use super::{write_atomically, Loaded, Storage};
+use crate::error::TodoError;
+use crate::todo::{new_id, single_priority, tree_order, Todo, DATE_FORMAT, PARENT_TAG};
+
+// Files of outliners such as Emacs Org and TaskPaper, where tasks are mixed
+// with headings, notes and whatever else the other tool keeps. todo-rs
+// reads the tasks and keeps everything else as it was, in place. A task
+// nested in another is its subtask, the `parent:` tag isn't written.
+pub trait Outline {
+    // The indent of a task at the top level
+    const TOP_LEVEL: &'static str;
+
+    fn parse(contents: &str) -> Vec<Block>;
+
+    // The lines of a task without its notes, in parts such as an Org
+    // headline, planning line and property drawer. `indent` is its Org
+    // stars or TaskPaper indentation, `block` where the task was read from,
+    // if it was.
+    fn render(todo: &Todo, indent: &str, block: Option<&TaskBlock>) -> Vec<String>;
+
+    // What note lines are indented with, under the task's own lines
+    fn notes_indent(indent: &str) -> String;
+
+    // The indent of a subtask of a task with this indent
+    fn deeper(indent: &str) -> String;
+
+    // How deep a line other than a task starts an item of the outline, such
+    // as an Org headline, none for lines that are part of the item above
+    fn depth(line: &str) -> Option<usize>;
+}
+
+pub enum Block {
+    // Lines that aren't tasks, written back untouched
+    Text(String),
+    Task(Box<TaskBlock>),
+}
+
+pub struct TaskBlock {
+    // The task as read
+    pub todo: Todo,
+    // The block as read, written back as is while the task is unchanged
+    pub text: String,
+    // Org stars or TaskPaper indentation
+    pub indent: String,
+    // The parts `Outline::render` writes, as read. A changed task keeps the
+    // parts that still render the same, with whatever formatting they had.
+    pub parts: Vec<String>,
+    // Org properties other than the ones todo-rs reads
+    pub properties: Vec<String>,
+    // Notes under the task, as read. The task's notes are the same text
+    // without the indentation.
+    pub notes: String,
+    // An id made up when reading for the task's subtasks to refer to, as it
+    // had none. It is written with the task's next change.
+    pub new_id: Option<String>,
+}
+
+pub struct OutlineStorage<F: Outline> {
+    path: PathBuf,
+    backups: usize,
+    // The file as last read or written
+    blocks: Vec<Block>,
+    format: PhantomData<F>,
+}
+
+impl<F: Outline> OutlineStorage<F> {
+    pub fn new(path: &Path, backups: usize) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            backups,
+            blocks: vec![],
+            format: PhantomData,
+        }
+    }
+
+    fn read(&mut self, contents: &[u8]) -> Loaded {
+        let mut malformed = vec![];
+        let contents = String::from_utf8(contents.to_vec()).unwrap_or_else(|e| {
+            malformed.push(TodoError::Parse { line: 1, message: String::from("invalid UTF-8") });
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        });
+        self.blocks = parse::<F>(&contents);
+        let todos = self.blocks.iter().filter_map(|block| match block {
+            Block::Task(task) => Some(Todo { id: task.new_id.clone().unwrap_or_else(|| task.todo.id.clone()), ..task.todo.clone() }),
+            Block::Text(_) => None,
+        });
+        Loaded { todos: todos.collect(), malformed }
+    }
+}
+
+impl<F: Outline> Storage for OutlineStorage<F> {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&mut self) -> Result<Loaded, TodoError> {
+        match fs::read(&self.path) {
+            Ok(contents) => Ok(self.read(&contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(self.read(&[])),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Unchanged tasks and other content stay as they were, changed tasks are
+    // rewritten in place and deleted ones left out. New tasks are added at
+    // the end, and new subtasks after the last subtask of their parent, a
+    // level deeper. A task that is no longer nested in its parent is moved
+    // to the right level.
+    fn save(&mut self, todos: &[Todo]) -> Result<(), TodoError> {
+        let mut written = vec![false; todos.len()];
+        let ids = todos.iter().map(|todo| todo.id.as_str()).collect::<HashSet<_>>();
+        // The file line by line, or task by task
+        let mut chunks: Vec<String> = vec![];
+        // The indent of every task written, and the chunk its subtasks end
+        // before
+        let mut placed: HashMap<&str, (String, usize)> = HashMap::new();
+        // The items enclosing the next line, innermost last, with the id of
+        // those that are tasks
+        let mut enclosing: Vec<(usize, Option<&str>)> = vec![];
+        for block in &self.blocks {
+            let task: &TaskBlock = match block {
+                Block::Text(text) => {
+                    for line in text.split_inclusive('\n') {
+                        if let Some(depth) = F::depth(line) {
+                            leave(&mut enclosing, depth);
+                            enclosing.push((depth, None));
+                        }
+                        chunks.push(line.to_string());
+                        if !line.trim().is_empty() {
+                            extend(&enclosing, &mut placed, chunks.len());
+                        }
+                    }
+                    continue;
+                },
+                Block::Task(task) => task,
+            };
+            let Some(index) = (0..todos.len()).find(|&index| !written[index] && is_same_task(task, &todos[index])) else {
+                continue;
+            };
+            written[index] = true;
+            let todo = &todos[index];
+
+            // A parent further down can't be nested in, the task stays put
+            let wanted = todo.parent().filter(|parent| ids.contains(parent));
+            let parent = wanted.and_then(|parent| placed.get_key_value(parent));
+            let mut indent = task.indent.clone();
+            leave(&mut enclosing, indent.len());
+            let nested_in = enclosing.iter().rev().find_map(|(_, id)| *id);
+            if nested_in != wanted && (wanted.is_none() || parent.is_some()) {
+                indent = match (parent, enclosing.iter().find_map(|(_, id)| *id)) {
+                    (Some((_, (parent_indent, _))), _) => F::deeper(parent_indent),
+                    // Out of every task it was nested in
+                    (None, Some(outermost)) => placed[outermost].0.clone(),
+                    (None, None) => indent,
+                };
+                leave(&mut enclosing, indent.len());
+            }
+
+            match *todo == task.todo && indent == task.indent {
+                true => chunks.push(task.text.clone()),
+                false => chunks.push(render::<F>(todo, &indent, Some(task))),
+            }
+            enclosing.push((indent.len(), Some(&todo.id)));
+            placed.insert(&todo.id, (indent, chunks.len()));
+            extend(&enclosing, &mut placed, chunks.len());
+        }
+
+        // New subtasks of new tasks are rendered right after their parent
+        let new = todos.iter().zip(&written).filter(|(_, written)| !**written).map(|(todo, _)| todo).collect::<Vec<_>>();
+        let mut inserts = vec![];
+        let mut root = (String::new(), 0);
+        for (todo, depth) in tree_order(&new, &HashSet::new()) {
+            if depth == 0 {
+                root = match todo.parent().and_then(|parent| placed.get(parent)) {
+                    Some((parent_indent, end)) => (F::deeper(parent_indent), *end),
+                    None => (F::TOP_LEVEL.to_string(), chunks.len()),
+                };
+            }
+            let indent = (0..depth).fold(root.0.clone(), |indent, _| F::deeper(&indent));
+            inserts.push((root.1, render::<F>(todo, &indent, None)));
+        }
+        inserts.sort_by_key(|(position, _)| *position);
+
+        let mut contents = String::new();
+        let mut inserts = inserts.into_iter().peekable();
+        for (position, chunk) in chunks.iter().enumerate() {
+            while let Some((_, text)) = inserts.next_if(|(at, _)| *at == position) {
+                contents.push_str(&text);
+            }
+            contents.push_str(chunk);
+        }
+        for (_, text) in inserts {
+            // The last line of a file may not end in one
+            if !contents.is_empty() && !contents.ends_with('\n') {
+                contents.push('\n');
+            }
+            contents.push_str(&text);
+        }
+
+        write_atomically(&self.path, contents.as_bytes(), self.backups)?;
+        self.blocks = parse::<F>(&contents);
+        Ok(())
+    }
+
+    fn load_bytes(&mut self, contents: &[u8]) -> Result<Loaded, TodoError> {
+        Ok(self.read(contents))
+    }
+
+    fn made_up_ids(&self) -> bool {
+        self.blocks.iter().any(|block| matches!(block, Block::Task(task) if task.new_id.is_some()))
+    }
+
+    // The parent is kept, it's where the task is in the file
+    fn normalize(&self, todo: &mut Todo) {
+        let rendered = render::<F>(todo, F::TOP_LEVEL, None);
+        if let Some(Block::Task(mut task)) = F::parse(&rendered).into_iter().next() {
+            task.todo.set_tag(PARENT_TAG, todo.parent());
+            *todo = task.todo;
+        }
+    }
+}
+
+// The blocks of a file, each task with the task it's nested in as its
+// parent. Parents without an id are given one.
+fn parse<F: Outline>(contents: &str) -> Vec<Block> {
+    let mut blocks = F::parse(contents);
+    let mut enclosing: Vec<(usize, Option<usize>)> = vec![];
+    let mut nested = vec![];
+    for (index, block) in blocks.iter().enumerate() {
+        match block {
+            Block::Text(text) => {
+                for depth in text.lines().filter_map(F::depth) {
+                    leave(&mut enclosing, depth);
+                    enclosing.push((depth, None));
+                }
+            },
+            Block::Task(task) => {
+                leave(&mut enclosing, task.indent.len());
+                if let Some(parent) = enclosing.iter().rev().find_map(|(_, task)| *task) {
+                    nested.push((index, parent));
+                }
+                enclosing.push((task.indent.len(), Some(index)));
+            },
+        }
+    }
+    for (index, parent) in nested {
+        let Block::Task(parent) = &mut blocks[parent] else {
+            continue;
+        };
+        if parent.todo.id.is_empty() && parent.new_id.is_none() {
+            parent.new_id = Some(new_id());
+        }
+        let id = parent.new_id.clone().unwrap_or_else(|| parent.todo.id.clone());
+        if let Block::Task(task) = &mut blocks[index] {
+            task.todo.set_tag(PARENT_TAG, Some(&id));
+        }
+    }
+    blocks
+}
+
+// Closes the items a line at `depth` isn't part of
+fn leave<T>(enclosing: &mut Vec<(usize, T)>, depth: usize) {
+    while enclosing.last().is_some_and(|(enclosing, _)| *enclosing >= depth) {
+        enclosing.pop();
+    }
+}
+
+// The tasks enclosing a line now end after it
+fn extend<'a>(enclosing: &[(usize, Option<&'a str>)], placed: &mut HashMap<&'a str, (String, usize)>, end: usize) {
+    for id in enclosing.iter().filter_map(|(_, id)| *id) {
+        if let Some((_, task_end)) = placed.get_mut(id) {
+            *task_end = end;
+        }
+    }
+}
+
+fn render<F: Outline>(todo: &Todo, indent: &str, block: Option<&TaskBlock>) -> String {
+    let parts = F::render(todo, indent, block);
+    let Some(block) = block else {
+        return parts.concat() + &indent_notes(&todo.notes, &F::notes_indent(indent));
+    };
+    let before = F::render(&block.todo, &block.indent, Some(block));
+    let mut rendered = String::new();
+    for (index, part) in parts.iter().enumerate() {
+        match (before.get(index) == Some(part), block.parts.get(index)) {
+            (true, Some(original)) => rendered.push_str(original),
+            _ => rendered.push_str(part),
+        }
+        // The last line of a file may not end in one
+        if !rendered.is_empty() && !rendered.ends_with('\n') {
+            rendered.push('\n');
+        }
+    }
+    match todo.notes == block.todo.notes && indent == block.indent {
+        true => rendered.push_str(&block.notes),
+        false => {
+            rendered.push_str(&indent_notes(&todo.notes, &F::notes_indent(indent)));
+            // Keep the blank lines that separated the task from the next
+            let content = block.notes.trim_end();
+            let blank_lines = block.notes[content.len()..].matches('\n').count().saturating_sub(usize::from(!content.is_empty()));
+            rendered.push_str(&"\n".repeat(blank_lines));
+        },
+    }
+    rendered
+}
+
+// The notes of a task from the lines under it, without their common
+// indentation
+pub fn read_notes(lines: &str) -> String {
+    let indent = lines
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let lines = lines.lines().map(|line| line.get(indent..).unwrap_or(line.trim_start()).trim_end()).collect::<Vec<_>>();
+    lines.join("\n").trim_matches('\n').to_string()
+}
+
+fn indent_notes(notes: &str, indent: &str) -> String {
+    notes
+        .lines()
+        .map(|line| match line.is_empty() {
+            true => format!("{}\n", indent.trim_end_matches(' ')),
+            false => format!("{}{}\n", indent, line),
+        })
+        .collect()
+}
+
+// Tasks read without an id got one right after loading, they are found by
+// their contents
+fn is_same_task(block: &TaskBlock, todo: &Todo) -> bool {
+    match block.todo.id.is_empty() {
+        true => block.todo == Todo { id: String::new(), ..todo.clone() },
+        false => block.todo.id == todo.id,
+    }
+}
+
+// What both formats keep out of the text of a task: the priority, the start
+// (todo.txt `t:`) and due dates, and the parent. Done tasks keep their priority in `pri:`.
+pub struct Fields {
+    pub text: String,
+    pub priority: Option<char>,
+    pub start: Option<NaiveDate>,
+    pub due: Option<NaiveDate>,
+}
+
+impl Fields {
+    pub fn of(todo: &Todo) -> Self {
+        let mut fields = Fields { text: String::new(), priority: todo.priority, start: None, due: None };
+        let mut words = vec![];
+        for word in todo.description.split_whitespace() {
+            let date = |prefix: &str| word.strip_prefix(prefix).and_then(|date| NaiveDate::parse_from_str(date, DATE_FORMAT).ok());
+            if let (Some(date), None) = (date("t:"), fields.start) {
+                fields.start = Some(date);
+            } else if let (Some(date), None) = (date("due:"), fields.due) {
+                fields.due = Some(date);
+            } else if let (Some(priority), true, None) = (word.strip_prefix("pri:").and_then(single_priority), todo.done, fields.priority) {
+                fields.priority = Some(priority);
+            } else if word.strip_prefix(PARENT_TAG).is_some_and(|rest| rest.starts_with(':')) {
+                // The nesting says it
+            } else {
+                words.push(word);
+            }
+        }
+        fields.text = words.join(" ");
+        fields
+    }
+
+    // The task these fields and the rest of the block describe
+    pub fn into_todo(self, id: String, done: bool, creation_date: Option<NaiveDate>, completion_date: Option<NaiveDate>, notes: String) -> Todo {
+        let mut words = vec![self.text];
+        if let (true, Some(priority)) = (done, self.priority) {
+            words.push(format!("pri:{}", priority));
+        }
+        if let Some(start) = self.start {
+            words.push(format!("t:{}", start.format(DATE_FORMAT)));
+        }
+        if let Some(due) = self.due {
+            words.push(format!("due:{}", due.format(DATE_FORMAT)));
+        }
+        let mut todo = Todo {
+            id,
+            done,
+            priority: self.priority.filter(|_| !done),
+            creation_date,
+            completion_date: completion_date.filter(|_| done),
+            notes,
+            ..Default::default()
+        };
+        todo.set_description(words.join(" ").trim());
+        todo
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::*;
+    use crate::storage::org::Org;
+
+    // Writes `contents` to a file of its own, loads it, lets `edit` change
+    // the tasks and returns what saving them wrote
+    pub fn round_trip<F: Outline>(name: &str, contents: &str, edit: impl FnOnce(&mut Vec<Todo>)) -> String {
+        let path = env::temp_dir().join(format!("todo-rs-{}-{}", process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let mut storage = OutlineStorage::<F>::new(&path, 0);
+        let mut todos = storage.load().unwrap().todos;
+        edit(&mut todos);
+        storage.save(&todos).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        saved
+    }
+
+    #[test]
+    fn notes_lose_their_common_indentation() {
+        assert_eq!(read_notes("   first\n     indented\n\n   last\n\n"), "first\n  indented\n\nlast");
+    }
+
+    #[test]
+    fn fields_keep_the_parent_out_of_the_text() {
+        let todo = Todo::parse("Call mom due:2023-05-03 parent:01H0ZKXJ6V4Q8Y1ZB1W0Q5N2X3 @phone");
+        let fields = Fields::of(&todo);
+        assert_eq!(fields.text, "Call mom @phone");
+        assert_eq!(fields.due, NaiveDate::from_ymd_opt(2023, 5, 3));
+    }
+
+    #[test]
+    fn nested_tasks_are_subtasks() {
+        let contents = "* TODO Parent\n** Heading\n*** TODO Child\n* TODO Other\n";
+        let loaded = OutlineStorage::<Org>::new(Path::new("unused.org"), 0).load_bytes(contents.as_bytes()).unwrap();
+        let [parent, child, other] = &loaded.todos[..] else {
+            panic!("expected three tasks");
+        };
+        assert!(!parent.id.is_empty());
+        assert_eq!(child.parent(), Some(parent.id.as_str()));
+        assert_eq!(other.parent(), None);
+    }
+
+    #[test]
+    fn new_subtasks_follow_the_subtasks_of_their_parent() {
+        let contents = "* TODO Parent\n  :PROPERTIES:\n  :ID:       p1\n  :END:\n** TODO Child\n   :PROPERTIES:\n   :ID:       c1\n   :END:\n* Notes\n";
+        let saved = round_trip::<Org>("subtask.org", contents, |todos| {
+            let mut todo = Todo::parse("New child parent:p1");
+            todo.id = String::from("c2");
+            todos.push(todo);
+        });
+        assert_eq!(
+            saved,
+            "* TODO Parent\n  :PROPERTIES:\n  :ID:       p1\n  :END:\n** TODO Child\n   :PROPERTIES:\n   :ID:       c1\n   :END:\n\
+             ** TODO New child\n   :PROPERTIES:\n   :ID:       c2\n   :END:\n* Notes\n",
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use chrono::NaiveDate;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{backup_path, rotate_backups, with_suffix, Loaded, Storage};
+use crate::error::TodoError;
+use crate::todo::{new_id, Todo, DATE_FORMAT};
+
+const SCHEMA_VERSION: i32 = 4;
+
+// Rows are keyed by task id, so a save only touches the tasks that changed.
+// Projects and contexts get a table of their own for querying the list by
+// tag from other tools.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS todos (
+        id              TEXT PRIMARY KEY NOT NULL,
+        position        INTEGER NOT NULL,
+        description     TEXT NOT NULL,
+        done            INTEGER NOT NULL,
+        priority        TEXT,
+        creation_date   TEXT,
+        completion_date TEXT,
+        notes           TEXT
+    );
+    CREATE INDEX IF NOT EXISTS todos_position ON todos (position);
+
+    CREATE TABLE IF NOT EXISTS todo_tags (
+        id    TEXT NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+        kind  TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS todo_tags_id ON todo_tags (id);
+";
+
+// The columns of a task after the id, as stored
+type Row = (i64, String, bool, Option<String>, Option<String>, Option<String>, Option<String>);
+
+pub struct SqliteStorage {
+    path: PathBuf,
+    backups: usize,
+    connection: Option<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn new(path: &Path, backups: usize) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            backups,
+            connection: None,
+        }
+    }
+
+    fn connection(&mut self) -> Result<&mut Connection, TodoError> {
+        if self.connection.is_none() {
+            let mut connection = Connection::open(&self.path)?;
+            connection.pragma_update(None, "journal_mode", "WAL")?;
+            connection.pragma_update(None, "synchronous", "NORMAL")?;
+            connection.pragma_update(None, "foreign_keys", "ON")?;
+
+            let version: i32 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+            if version > SCHEMA_VERSION {
+                return Err(TodoError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported schema version {}", version),
+                )));
+            }
+            if version == 1 {
+                // Version 2 added ids
+                connection.execute_batch("ALTER TABLE todos ADD COLUMN id TEXT")?;
+            }
+            if (1..3).contains(&version) {
+                // Version 3 added notes
+                connection.execute_batch("ALTER TABLE todos ADD COLUMN notes TEXT")?;
+            }
+            if (1..4).contains(&version) {
+                key_by_id(&mut connection)?;
+            }
+            connection.execute_batch(SCHEMA)?;
+            connection.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+            self.connection = Some(connection);
+        }
+        Ok(self.connection.as_mut().unwrap())
+    }
+
+    // SQLite copies a consistent snapshot itself, with whatever is still in
+    // the WAL, into the newest of the rotating backups
+    fn back_up(&mut self) -> Result<(), TodoError> {
+        if self.backups == 0 || !self.path.exists() {
+            return Ok(());
+        }
+        let backup = backup_path(&self.path, 1);
+        let target = backup.to_str()
+            .ok_or_else(|| TodoError::Unsupported(format!("can't back up to {}, the path isn't UTF-8", backup.display())))?
+            .to_string();
+        rotate_backups(&self.path, self.backups)?;
+        match fs::remove_file(&backup) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {},
+        }
+        self.connection()?.execute("VACUUM INTO ?1", [target])?;
+        Ok(())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&mut self) -> Result<Loaded, TodoError> {
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(
+            "SELECT description, done, priority, creation_date, completion_date, id, notes
+             FROM todos ORDER BY position",
+        )?;
+        let rows = statement.query_map([], |row| {
+            let description: String = row.get(0)?;
+            let priority: Option<String> = row.get(2)?;
+            let mut todo = Todo {
+                id: row.get(5)?,
+                done: row.get(1)?,
+                priority: priority.and_then(|p| p.chars().next()),
+                creation_date: row.get::<_, Option<String>>(3)?.and_then(|d| parse_date(&d)),
+                completion_date: row.get::<_, Option<String>>(4)?.and_then(|d| parse_date(&d)),
+                notes: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                ..Default::default()
+            };
+            todo.set_description(&description);
+            Ok(todo)
+        })?;
+
+        let todos = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok(Loaded { todos, malformed: vec![] })
+    }
+
+    // SQLite can only read a file, so the database is copied to one first
+    fn load_bytes(&mut self, contents: &[u8]) -> Result<Loaded, TodoError> {
+        let copy = with_suffix(&self.path, ".restore");
+        fs::write(&copy, contents)?;
+        let loaded = SqliteStorage::new(&copy, 0).load();
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(with_suffix(&copy, suffix));
+        }
+        loaded
+    }
+
+    // Inserts, updates and deletes only the rows of tasks that changed
+    fn save(&mut self, todos: &[Todo]) -> Result<(), TodoError> {
+        self.back_up()?;
+        let connection = self.connection()?;
+        let transaction = connection.transaction()?;
+        {
+            let mut stored = transaction
+                .prepare("SELECT id, position, description, done, priority, creation_date, completion_date, notes FROM todos")?
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, (row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?)))
+                })?
+                .collect::<Result<HashMap<String, Row>, _>>()?;
+            let mut insert = transaction.prepare(
+                "INSERT INTO todos (id, position, description, done, priority, creation_date, completion_date, notes)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            let mut update = transaction.prepare(
+                "UPDATE todos SET position = ?2, description = ?3, done = ?4, priority = ?5,
+                 creation_date = ?6, completion_date = ?7, notes = ?8 WHERE id = ?1",
+            )?;
+            let mut delete_tags = transaction.prepare("DELETE FROM todo_tags WHERE id = ?1")?;
+            for (position, todo) in todos.iter().enumerate() {
+                let row = row(position, todo);
+                let (p, description, done, priority, creation_date, completion_date, notes) = &row;
+                let values = params![todo.id, p, description, done, priority, creation_date, completion_date, notes];
+                match stored.remove(&todo.id) {
+                    Some(old) if old == row => {},
+                    Some(old) => {
+                        update.execute(values)?;
+                        if old.1 != row.1 {
+                            delete_tags.execute([&todo.id])?;
+                            insert_tags(&transaction, todo)?;
+                        }
+                    },
+                    None => {
+                        insert.execute(values)?;
+                        insert_tags(&transaction, todo)?;
+                    },
+                }
+            }
+            let mut delete = transaction.prepare("DELETE FROM todos WHERE id = ?1")?;
+            for id in stored.keys() {
+                delete.execute([id])?;
+            }
+        }
+        transaction.commit()?;
+        // Fold the WAL back in so the database file alone is the whole list,
+        // as copied into history
+        connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+}
+
+fn row(position: usize, todo: &Todo) -> Row {
+    (
+        position as i64,
+        todo.description.clone(),
+        todo.done,
+        todo.priority.map(String::from),
+        todo.creation_date.map(|d| d.format(DATE_FORMAT).to_string()),
+        todo.completion_date.map(|d| d.format(DATE_FORMAT).to_string()),
+        Some(todo.notes.clone()).filter(|notes| !notes.is_empty()),
+    )
+}
+
+fn insert_tags(connection: &Connection, todo: &Todo) -> rusqlite::Result<()> {
+    let mut insert = connection.prepare_cached("INSERT INTO todo_tags (id, kind, value) VALUES (?1, ?2, ?3)")?;
+    for project in &todo.projects {
+        insert.execute(params![todo.id, "project", project])?;
+    }
+    for context in &todo.contexts {
+        insert.execute(params![todo.id, "context", context])?;
+    }
+    Ok(())
+}
+
+// Up to version 3 rows were keyed by their position. Rows from before ids
+// get one here, and the tags are read again from the descriptions.
+fn key_by_id(connection: &mut Connection) -> Result<(), TodoError> {
+    let transaction = connection.transaction()?;
+    let missing = transaction
+        .prepare("SELECT position FROM todos WHERE id IS NULL OR id = ''")?
+        .query_map([], |row| row.get::<_, i64>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for position in missing {
+        transaction.execute("UPDATE todos SET id = ?1 WHERE position = ?2", params![new_id(), position])?;
+    }
+    transaction.execute_batch("DROP TABLE todo_tags; ALTER TABLE todos RENAME TO todos_by_position;")?;
+    transaction.execute_batch(SCHEMA)?;
+    transaction.execute_batch(
+        "INSERT INTO todos (id, position, description, done, priority, creation_date, completion_date, notes)
+         SELECT id, position, description, done, priority, creation_date, completion_date, notes FROM todos_by_position;
+         DROP TABLE todos_by_position;",
+    )?;
+    let tasks = transaction
+        .prepare("SELECT id, description FROM todos")?
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (id, description) in tasks {
+        let mut todo = Todo { id, ..Default::default() };
+        todo.set_description(&description);
+        insert_tags(&transaction, &todo)?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+fn parse_date(s: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(s, DATE_FORMAT).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn saves_update_the_rows_of_changed_tasks() {
+        let path = env::temp_dir().join(format!("todo-rs-{}-rows.db", process::id()));
+        let mut storage = SqliteStorage::new(&path, 1);
+        let mut todos = ["Call mom +family", "Feed the cat @home", "Water plants"].map(|description| Todo {
+            id: new_id(),
+            ..Todo::parse(description)
+        }).to_vec();
+        storage.save(&todos).unwrap();
+
+        todos.remove(0);
+        todos[0].set_description("Feed the dog @home @garden");
+        todos[1].mark_as_done();
+        todos.insert(0, Todo { id: new_id(), ..Todo::parse("First +new") });
+        storage.save(&todos).unwrap();
+
+        let loaded = storage.load().unwrap().todos;
+        let descriptions = loaded.iter().map(|todo| todo.description.as_str()).collect::<Vec<_>>();
+        assert_eq!(descriptions, ["First +new", "Feed the dog @home @garden", "Water plants"]);
+        assert!(loaded[2].is_done());
+        let tags = storage.connection().unwrap()
+            .prepare("SELECT value FROM todo_tags ORDER BY value").unwrap()
+            .query_map([], |row| row.get::<_, String>(0)).unwrap()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(tags, ["garden", "home", "new"]);
+        // The list as it was before the second save
+        assert_eq!(storage.load_backup(1).unwrap().todos.len(), 3);
+        assert_eq!(storage.load_backup(1).unwrap().todos[0].description, "Call mom +family");
+
+        drop(storage);
+        for suffix in ["", "-wal", "-shm", ".bak.1"] {
+            let _ = fs::remove_file(with_suffix(&path, suffix));
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use chrono::NaiveDate;
+
+use super::outline::{read_notes, Block, Fields, Outline, OutlineStorage, TaskBlock};
+use crate::todo::{Todo, DATE_FORMAT};
+
+// TaskPaper files. Lines starting with `- ` are tasks, more indented lines
+// under a task are its notes, and projects (`Name:`) and anything else are
+// kept:
+//   Home:
+//   	- Call mom @phone @priority(A) @due(2023-05-03) @done(2023-05-02)
+//   		Her new number is in the address book
+// `@done`, `@priority`, `@start`, `@due`, `@created` and `@id` are read into
+// the task, other tags stay in its text as todo.txt `@contexts`. A task
+// indented under another is its subtask.
+pub type TaskPaperStorage = OutlineStorage<TaskPaper>;
+
+pub struct TaskPaper;
+
+impl Outline for TaskPaper {
+    const TOP_LEVEL: &'static str = "";
+
+    fn parse(contents: &str) -> Vec<Block> {
+        let lines = contents.split_inclusive('\n').collect::<Vec<_>>();
+        let mut blocks = vec![];
+        let mut text = String::new();
+        let mut i = 0;
+        while i < lines.len() {
+            let Some((indent, _)) = task_line(lines[i]) else {
+                text.push_str(lines[i]);
+                i += 1;
+                continue;
+            };
+            if !text.is_empty() {
+                blocks.push(Block::Text(std::mem::take(&mut text)));
+            }
+            let start = i;
+            i += 1;
+            // Blank lines in notes keep the indentation
+            while i < lines.len() && task_line(lines[i]).is_none() && indentation(lines[i]).len() > indent.len() {
+                i += 1;
+            }
+            blocks.push(Block::Task(Box::new(read_task(&lines[start..i]))));
+        }
+        if !text.is_empty() {
+            blocks.push(Block::Text(text));
+        }
+        blocks
+    }
+
+    fn render(todo: &Todo, indent: &str, _block: Option<&TaskBlock>) -> Vec<String> {
+        let fields = Fields::of(todo);
+        let mut line = format!("{}- {}", indent, fields.text);
+        if let Some(priority) = fields.priority {
+            line.push_str(&format!(" @priority({})", priority));
+        }
+        if let Some(date) = fields.start {
+            line.push_str(&format!(" @start({})", date.format(DATE_FORMAT)));
+        }
+        if let Some(date) = fields.due {
+            line.push_str(&format!(" @due({})", date.format(DATE_FORMAT)));
+        }
+        if let Some(date) = todo.creation_date {
+            line.push_str(&format!(" @created({})", date.format(DATE_FORMAT)));
+        }
+        match (todo.is_done(), todo.completion_date) {
+            (true, Some(date)) => line.push_str(&format!(" @done({})", date.format(DATE_FORMAT))),
+            (true, None) => line.push_str(" @done"),
+            _ => {},
+        }
+        if !todo.id.is_empty() {
+            line.push_str(&format!(" @id({})", todo.id));
+        }
+        line.push('\n');
+        vec![line]
+    }
+
+    fn notes_indent(indent: &str) -> String {
+        format!("{}\t", indent)
+    }
+
+    fn deeper(indent: &str) -> String {
+        format!("{}\t", indent)
+    }
+
+    // Projects and notes outside tasks are items too
+    fn depth(line: &str) -> Option<usize> {
+        match line.trim().is_empty() {
+            true => None,
+            false => Some(indentation(line).len()),
+        }
+    }
+}
+
+fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+// The indentation and the text of a `- task` line
+fn task_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let indent = indentation(line);
+    let rest = &line[indent.len()..];
+    match rest.strip_prefix("- ") {
+        Some(text) => Some((indent, text)),
+        None if rest == "-" => Some((indent, "")),
+        None => None,
+    }
+}
+
+fn read_task(lines: &[&str]) -> TaskBlock {
+    let (indent, text) = task_line(lines[0]).unwrap_or(("", ""));
+    let mut fields = Fields { text: String::new(), priority: None, start: None, due: None };
+    let (mut id, mut done, mut created, mut completed) = (String::new(), false, None, None);
+    let mut words = vec![];
+    for (word, tag) in split_tags(text) {
+        let date = tag.and_then(|(_, value)| value).and_then(|value| NaiveDate::parse_from_str(value, DATE_FORMAT).ok());
+        match (tag, date) {
+            (Some(("done", _)), date) => (done, completed) = (true, date),
+            (Some(("start", _)), Some(date)) => fields.start = Some(date),
+            (Some(("due", _)), Some(date)) => fields.due = Some(date),
+            (Some(("created", _)), Some(date)) => created = Some(date),
+            (Some(("id", Some(value))), _) if !value.is_empty() => id = value.to_string(),
+            (Some(("priority", Some(value))), _) if priority(value).is_some() => fields.priority = priority(value),
+            _ => words.push(word),
+        }
+    }
+    fields.text = words.join(" ");
+
+    TaskBlock {
+        todo: fields.into_todo(id, done, created, completed, read_notes(&lines[1..].concat())),
+        text: lines.concat(),
+        indent: indent.to_string(),
+        parts: vec![lines[0].to_string()],
+        properties: vec![],
+        notes: lines[1..].concat(),
+        new_id: None,
+    }
+}
+
+// The name and the value of a tag
+type Tag<'a> = (&'a str, Option<&'a str>);
+
+// The words of a task, each with its name and value when it's a tag.
+// Values may contain spaces: `@due(next week)`.
+fn split_tags(text: &str) -> Vec<(&str, Option<Tag<'_>>)> {
+    let mut words = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let mut end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let mut tag = None;
+        if let Some(tagged) = rest.strip_prefix('@') {
+            let name_end = tagged.find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))).unwrap_or(tagged.len());
+            let name = &tagged[..name_end];
+            match tagged[name_end..].strip_prefix('(').and_then(|value| value.find(')').map(|close| &value[..close])) {
+                Some(value) => {
+                    end = 1 + name_end + 1 + value.len() + 1;
+                    tag = Some((name, Some(value)));
+                },
+                None if name_end == end - 1 => tag = Some((name, None)),
+                None => {},
+            }
+        }
+        words.push((&rest[..end], tag.filter(|(name, _)| !name.is_empty())));
+        rest = rest[end..].trim_start();
+    }
+    words
+}
+
+// `@priority(A)`, or a number with 1 the highest
+fn priority(value: &str) -> Option<char> {
+    match value.parse::<u8>() {
+        Ok(n @ 1..=26) => Some((b'A' + n - 1) as char),
+        _ => value.chars().next().filter(|c| c.is_ascii_uppercase() && value.len() == 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::outline::tests::round_trip;
+
+    const FILE: &str = "\
+Home:
+\t- Call mom @phone @priority(A) @due(2023-05-03) @id(call)
+\t\tHer new number is in the address book
+\t\t- Find the address book @id(find)
+
+\t- Feed the cat @done(2023-05-02) @id(feed)
+Work:
+\tA note about work
+\t- Last line without a newline";
+
+    #[test]
+    fn an_unchanged_file_is_written_back_as_it_was() {
+        assert_eq!(round_trip::<TaskPaper>("unchanged.taskpaper", FILE, |_| {}), FILE);
+    }
+
+    #[test]
+    fn an_edited_task_keeps_what_surrounds_it() {
+        let saved = round_trip::<TaskPaper>("edited.taskpaper", FILE, |todos| {
+            let feed = todos.iter_mut().find(|todo| todo.id == "feed").unwrap();
+            feed.set_description("Feed the dog");
+        });
+        assert_eq!(saved, FILE.replace("Feed the cat", "Feed the dog"));
+    }
+
+    #[test]
+    fn priority_due_date_and_subtasks_are_read() {
+        let saved = round_trip::<TaskPaper>("read.taskpaper", FILE, |todos| {
+            let call = todos.iter().find(|todo| todo.id == "call").unwrap();
+            assert_eq!(call.priority, Some('A'));
+            assert_eq!(call.due_date(), NaiveDate::from_ymd_opt(2023, 5, 3));
+            assert!(call.has_tag("@phone"));
+            assert_eq!(call.notes, "Her new number is in the address book");
+            let find = todos.iter().find(|todo| todo.id == "find").unwrap();
+            assert_eq!(find.parent(), Some("call"));
+        });
+        assert_eq!(saved, FILE);
+    }
+
+    #[test]
+    fn priority_and_due_date_survive_an_edit() {
+        let saved = round_trip::<TaskPaper>("rendered.taskpaper", FILE, |todos| {
+            let call = todos.iter_mut().find(|todo| todo.id == "call").unwrap();
+            call.set_description("Call dad @phone due:2023-05-03");
+        });
+        assert!(saved.contains("\t- Call dad @phone @priority(A) @due(2023-05-03) @id(call)\n\t\tHer new number"));
+    }
+
+    #[test]
+    fn tag_values_may_contain_spaces() {
+        let words = split_tags("Plan @due(next week) @home");
+        assert_eq!(words.iter().map(|(word, _)| *word).collect::<Vec<_>>(), ["Plan", "@due(next week)", "@home"]);
+        assert_eq!(words[1].1, Some(("due", Some("next week"))));
+    }
+}
@@ -0,0 +1,264 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use super::{write_atomically, Loaded, Storage};
+use crate::error::TodoError;
+use crate::todo::Todo;
+
+// Older versions of todo-rs started their files with this line. It is
+// skipped when read and no longer written, so lists stay plain todo.txt.
+const FORMAT_VERSION: u32 = 2;
+const FORMAT_HEADER: &str = "# todo-rs format";
+
+pub struct TextStorage {
+    path: PathBuf,
+    backups: usize,
+}
+
+impl TextStorage {
+    pub fn new(path: &Path, backups: usize) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            backups,
+        }
+    }
+}
+
+impl Storage for TextStorage {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&mut self) -> Result<Loaded, TodoError> {
+        load_file(&self.path)
+    }
+
+    fn save(&mut self, todos: &[Todo]) -> Result<(), TodoError> {
+        write_atomically(&self.path, serialize(todos).as_bytes(), self.backups)?;
+        Ok(())
+    }
+
+    fn load_bytes(&mut self, contents: &[u8]) -> Result<Loaded, TodoError> {
+        parse(contents)
+    }
+}
+
+pub(super) fn serialize(todos: &[Todo]) -> String {
+    let mut contents = String::new();
+    for todo in todos {
+        contents.push_str(&escape(&todo.to_string()));
+        contents.push('\n');
+    }
+    contents
+}
+
+pub(super) fn parse(contents: &[u8]) -> Result<Loaded, TodoError> {
+    parse_lines(&split_lines(contents)?)
+}
+
+fn load_file(path: &Path) -> Result<Loaded, TodoError> {
+    match File::open(path) {
+        Ok(file) => parse_lines(&split_lines(file)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Loaded { todos: vec![], malformed: vec![] }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn parse_lines(lines: &[Vec<u8>]) -> Result<Loaded, TodoError> {
+    let mut malformed = vec![];
+    let mut decoded = vec![];
+    for (index, raw) in lines.iter().enumerate() {
+        let line_number = index + 1;
+        let line = match std::str::from_utf8(raw) {
+            Ok(line) => line.to_string(),
+            Err(_) => {
+                malformed.push(TodoError::Parse { line: line_number, message: "invalid UTF-8".to_string() });
+                String::from_utf8_lossy(raw).into_owned()
+            }
+        };
+        if !line.trim().is_empty() {
+            decoded.push((line_number, line));
+        }
+    }
+
+    if let Some((line_number, line)) = decoded.first() {
+        if let Some(version) = line.strip_prefix(FORMAT_HEADER) {
+            // Saving a newer layout as this one could lose whatever it added
+            match version.trim().parse::<u32>() {
+                Ok(version) if version <= FORMAT_VERSION => {},
+                Ok(version) => return Err(TodoError::Parse {
+                    line: *line_number,
+                    message: format!("written in format {} by a newer todo-rs, this one only reads up to format {}", version, FORMAT_VERSION),
+                }),
+                Err(_) => malformed.push(TodoError::Parse {
+                    line: *line_number,
+                    message: format!("unsupported format version `{}`", version.trim()),
+                }),
+            }
+            decoded.remove(0);
+        }
+    }
+
+    let todos = if is_legacy(&decoded) {
+        decoded.iter().map(|(line_number, line)| match line.split_once(',') {
+            Some((status, description)) => {
+                if status != "x" && status != " " {
+                    malformed.push(TodoError::Parse {
+                        line: *line_number,
+                        message: format!("unknown status `{}`", status),
+                    });
+                }
+                Todo {
+                    done: status == "x",
+                    ..Todo::parse(description)
+                }
+            },
+            None => {
+                malformed.push(TodoError::Parse { line: *line_number, message: "missing `,` separator".to_string() });
+                Todo::parse(line)
+            }
+        }).collect()
+    } else {
+        decoded.iter().map(|(_, line)| Todo::parse(&unescape(line))).collect()
+    };
+
+    Ok(Loaded { todos, malformed })
+}
+
+// The `x,description` layout of the first versions of todo-rs. A todo.txt
+// task may well start with `x,` too, so most of the lines have to look like it.
+fn is_legacy(lines: &[(usize, String)]) -> bool {
+    let legacy = |line: &str| line.starts_with("x,") || line.starts_with(" ,");
+    match lines.first() {
+        Some((_, first)) if legacy(first) => lines.iter().filter(|(_, line)| legacy(line)).count() * 2 >= lines.len(),
+        _ => false,
+    }
+}
+
+// Backslashes are only doubled where they would otherwise read as an escape,
+// so paths and other backslashes in the file look the same to other tools.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), None | Some('\\' | 'n' | 'r' | 't')) => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Unknown escapes and a trailing backslash are kept as they are, they are
+// most likely part of a task written by another tool.
+pub fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            _ => {
+                unescaped.push('\\');
+                continue;
+            },
+        }
+        chars.next();
+    }
+    unescaped
+}
+
+fn split_lines(reader: impl Read) -> io::Result<Vec<Vec<u8>>> {
+    let mut reader = BufReader::new(reader);
+    let mut lines = vec![];
+    let mut buf = vec![];
+    while reader.read_until(b'\n', &mut buf)? > 0 {
+        if buf.ends_with(b"\n") {
+            buf.pop();
+        }
+        if buf.ends_with(b"\r") {
+            buf.pop();
+        }
+        lines.push(std::mem::take(&mut buf));
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(contents: &[u8]) -> Loaded {
+        parse(contents).unwrap()
+    }
+
+    fn descriptions(loaded: &Loaded) -> Vec<&str> {
+        loaded.todos.iter().map(|todo| todo.description.as_str()).collect()
+    }
+
+    #[test]
+    fn line_breaks_and_backslashes_are_escaped() {
+        let mut todo = Todo::parse(r"Copy C:\new\ and C:\path, then a\\b");
+        todo.notes = String::from("first\n\tsecond\\n");
+        let written = serialize(&[todo.clone()]);
+        // Only the backslashes that would read as an escape are doubled
+        assert_eq!(written, concat!(r"Copy C:\\new\ and C:\path, then a\\\b\nfirst\n\tsecond\\n", "\n"));
+        let loaded = read(written.as_bytes());
+        assert_eq!(loaded.todos[0].description, todo.description);
+        assert_eq!(loaded.todos[0].notes, todo.notes);
+        assert!(loaded.malformed.is_empty());
+    }
+
+    #[test]
+    fn unknown_escapes_are_kept() {
+        assert_eq!(unescape(r"C:\path\q and \"), r"C:\path\q and \");
+        assert_eq!(unescape(r"a\\b\tc"), "a\\b\tc");
+    }
+
+    #[test]
+    fn no_header_is_written() {
+        let written = serialize(&[Todo::parse("(A) Call mom")]);
+        assert_eq!(written, "(A) Call mom\n");
+    }
+
+    #[test]
+    fn old_headers_are_skipped_and_newer_ones_refused() {
+        let loaded = read(b"# todo-rs format 2\nCall mom\n");
+        assert_eq!(descriptions(&loaded), ["Call mom"]);
+        assert!(loaded.malformed.is_empty());
+        assert!(parse(b"# todo-rs format 3\nCall mom\n").is_err());
+    }
+
+    #[test]
+    fn legacy_lines_are_read_when_most_lines_look_like_them() {
+        let loaded = read(b"x,Feed the cat\n ,Call mom\nno comma\n");
+        assert_eq!(descriptions(&loaded), ["Feed the cat", "Call mom", "no comma"]);
+        assert!(loaded.todos[0].done && !loaded.todos[1].done);
+        assert_eq!(loaded.malformed.len(), 1);
+
+        // A todo.txt list that happens to start with `x,`
+        let loaded = read(b"x,y coordinates\nCall mom\nFeed the cat\n");
+        assert_eq!(descriptions(&loaded), ["x,y coordinates", "Call mom", "Feed the cat"]);
+        assert!(loaded.malformed.is_empty());
+    }
+
+    #[test]
+    fn malformed_lines_are_kept() {
+        let loaded = read(b"Call mom\nBad \xff byte\r\n\nFeed the cat");
+        assert_eq!(descriptions(&loaded), ["Call mom", "Bad \u{fffd} byte", "Feed the cat"]);
+        let [TodoError::Parse { line, .. }] = &loaded.malformed[..] else {
+            panic!("expected one malformed line");
+        };
+        assert_eq!(*line, 2);
+    }
+}
@@ -1,23 +1,199 @@
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
-use std::ptr::eq;
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, Utc};
+use git2::Oid;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use ulid::Ulid;
 
+use crate::dates::expand_due;
+use crate::error::TodoError;
+use crate::history::{History, Version};
+use crate::journal::{Journal, Operation, Replayed, DEFAULT_COMPACT_AFTER};
+use crate::lists::{archive_path, trash_path};
+use crate::lock::FileLock;
+use crate::merge::merge;
+use crate::storage::{self, Backend, EncryptedStorage, Loaded, Storage, DEFAULT_BACKUPS};
 
+pub const DATE_FORMAT: &str = "%Y-%m-%d";
+
+// Tasks in the trash carry a `deleted:20231005T143000Z` tag, in UTC. The
+// basic ISO 8601 format has no `:`, which a tag value can't contain.
+const DELETED_TAG: &str = "deleted";
+const DELETED_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+// Subtasks point at the id of their parent with a `parent:` tag
+pub const PARENT_TAG: &str = "parent";
+
+// A single task in todo.txt format:
+//   x (A) 2023-05-02 2023-05-01 Call mom +family @phone due:2023-05-03
+// See https://github.com/todotxt/todo.txt for the full grammar. Notes follow
+// the first line break, which the text storage and the journal escape.
+#[derive(Clone, Default, PartialEq)]
 pub struct Todo {
+    // Stable identifier, written as an `id:` tag. Tasks read without one
+    // get a fresh ULID when the list is loaded.
+    pub id: String,
     pub description: String,
     pub done: bool,
+    pub priority: Option<char>,
+    pub completion_date: Option<NaiveDate>,
+    pub creation_date: Option<NaiveDate>,
+    pub projects: Vec<String>,
+    pub contexts: Vec<String>,
+    pub tags: Vec<(String, String)>,
+    // Free-form text under the task, possibly several lines
+    pub notes: String,
 }
 
 impl Todo {
-    fn new(description: &str) -> Self { 
-        Self { 
-            description: String::from(description), 
-            done: false 
-        } 
+    fn new(description: &str) -> Self {
+        let mut todo = Todo::parse(description);
+        if todo.id.is_empty() {
+            todo.id = new_id();
+        }
+        if todo.creation_date.is_none() {
+            todo.creation_date = Some(Local::now().date_naive());
+        }
+        todo
+    }
+
+    pub fn parse(line: &str) -> Self {
+        let (line, notes) = line.split_once('\n').unwrap_or((line, ""));
+        let mut rest = line.trim_end();
+        let mut done = false;
+        let mut priority = None;
+        let mut completion_date = None;
+        let mut creation_date = None;
+
+        if let Some(stripped) = rest.strip_prefix("x ") {
+            done = true;
+            rest = stripped;
+        }
+        if let Some((p, stripped)) = parse_priority(rest) {
+            priority = Some(p);
+            rest = stripped;
+        }
+        if let Some((first, stripped)) = parse_date(rest) {
+            rest = stripped;
+            if done {
+                // A completed task lists its completion date first,
+                // optionally followed by its creation date.
+                completion_date = Some(first);
+                if let Some((second, stripped)) = parse_date(rest) {
+                    creation_date = Some(second);
+                    rest = stripped;
+                }
+            } else {
+                creation_date = Some(first);
+            }
+        }
+
+        // The id is kept out of the description, it's not meant to be read
+        let mut id = None;
+        let words = rest
+            .split(' ')
+            .filter(|word| match word.strip_prefix("id:") {
+                Some(value) if id.is_none() && !value.is_empty() => {
+                    id = Some(value.to_string());
+                    false
+                },
+                _ => true,
+            })
+            .collect::<Vec<_>>();
+
+        let mut todo = Self {
+            id: id.unwrap_or_default(),
+            done,
+            priority,
+            completion_date,
+            creation_date,
+            notes: clean_notes(notes),
+            ..Default::default()
+        };
+        todo.set_description(&words.join(" "));
+        todo
+    }
+
+    pub fn set_description(&mut self, description: &str) {
+        self.description = description.to_string();
+        self.projects.clear();
+        self.contexts.clear();
+        self.tags.clear();
+        for word in description.split_whitespace() {
+            if let Some(project) = word.strip_prefix('+').filter(|p| !p.is_empty()) {
+                self.projects.push(project.to_string());
+            } else if let Some(context) = word.strip_prefix('@').filter(|c| !c.is_empty()) {
+                self.contexts.push(context.to_string());
+            } else if let Some((key, value)) = parse_tag(word) {
+                self.tags.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    // Replaces the `key:` tag in the description, `None` removes it
+    pub fn set_tag(&mut self, key: &str, value: Option<&str>) {
+        let prefix = format!("{}:", key);
+        let mut words = self.description
+            .split_whitespace()
+            .filter(|word| !word.starts_with(&prefix))
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if let Some(value) = value {
+            words.push(format!("{}{}", prefix, value));
+        }
+        self.set_description(&words.join(" "));
+    }
+
+    // The id of the task this is a subtask of
+    pub fn parent(&self) -> Option<&str> {
+        self.tag(PARENT_TAG)
+    }
+
+    // The `due:` tag, when it's a date
+    pub fn due_date(&self) -> Option<NaiveDate> {
+        self.tag("due").and_then(|due| NaiveDate::parse_from_str(due, DATE_FORMAT).ok())
+    }
+
+    // Whether the task has a `+project` or `@context`, given with its sign
+    pub fn has_tag(&self, tag: &str) -> bool {
+        match (tag.strip_prefix('+'), tag.strip_prefix('@')) {
+            (Some(project), _) => self.projects.iter().any(|p| p == project),
+            (_, Some(context)) => self.contexts.iter().any(|c| c == context),
+            _ => false,
+        }
+    }
+
+    // Whether the description contains `query`, ignoring case
+    pub fn matches(&self, query: &str) -> bool {
+        self.description.to_lowercase().contains(&query.to_lowercase())
+    }
+
+    // When the task was moved to the trash
+    pub fn deleted_at(&self) -> Option<DateTime<Utc>> {
+        let deleted = self.tag(DELETED_TAG)?;
+        NaiveDateTime::parse_from_str(deleted, DELETED_FORMAT).ok().map(|time| time.and_utc())
     }
 
     pub fn mark_as_done(&mut self) {
+        if self.done {
+            return;
+        }
         self.done = true;
+        self.completion_date = Some(Local::now().date_naive());
+        // Completed tasks don't carry a priority, keep it as a `pri:` tag
+        // so it can be restored when the task is reopened.
+        if let Some(priority) = self.priority.take() {
+            self.set_tag("pri", Some(&priority.to_string()));
+        }
+    }
+
+    // The priority, or the one a done task keeps in `pri:`
+    pub fn effective_priority(&self) -> Option<char> {
+        self.priority.or_else(|| self.tag("pri").and_then(single_priority))
     }
 
     pub fn is_done(&self) -> bool {
@@ -25,63 +201,784 @@ impl Todo {
     }
 
     pub fn mark_as_undone(&mut self) {
+        if !self.done {
+            return;
+        }
         self.done = false;
+        self.completion_date = None;
+        if let Some(priority) = self.tag("pri").and_then(single_priority) {
+            self.set_tag("pri", None);
+            self.priority = Some(priority);
+        }
+    }
+}
+
+impl fmt::Display for Todo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.done {
+            write!(f, "x ")?;
+        }
+        if let Some(priority) = self.priority {
+            write!(f, "({}) ", priority)?;
+        }
+        // A single date on a done task reads back as its completion date, so
+        // a done task with only a creation date, such as an old `x,` line,
+        // gets it twice rather than losing it
+        if let Some(date) = self.completion_date.or(self.creation_date.filter(|_| self.done)) {
+            write!(f, "{} ", date.format(DATE_FORMAT))?;
+        }
+        if let Some(date) = self.creation_date {
+            write!(f, "{} ", date.format(DATE_FORMAT))?;
+        }
+        write!(f, "{}", self.description)?;
+        if !self.id.is_empty() {
+            write!(f, " id:{}", self.id)?;
+        }
+        if !self.notes.is_empty() {
+            write!(f, "\n{}", self.notes)?;
+        }
+        Ok(())
     }
 }
+
+pub fn new_id() -> String {
+    Ulid::new().to_string()
+}
+
+// Unix line breaks and no blank lines or spaces around them
+pub fn clean_notes(notes: &str) -> String {
+    notes.replace("\r\n", "\n").trim_end().trim_start_matches('\n').to_string()
+}
+
+// Tasks with their depth, each subtask right after its parent. Tasks whose
+// parent isn't among `todos` are at the top level, and the subtasks of
+// `collapsed` tasks are left out.
+pub fn tree_order<'a>(todos: &[&'a Todo], collapsed: &HashSet<String>) -> Vec<(&'a Todo, usize)> {
+    let ids = todos.iter().map(|todo| todo.id.as_str()).collect::<HashSet<_>>();
+    let mut children: HashMap<&str, Vec<&'a Todo>> = HashMap::new();
+    for todo in todos {
+        if let Some(parent) = todo.parent().filter(|parent| ids.contains(parent)) {
+            children.entry(parent).or_default().push(todo);
+        }
+    }
+    let mut visited = HashSet::new();
+    let mut ordered = vec![];
+    let roots = todos.iter().filter(|todo| !todo.parent().is_some_and(|parent| ids.contains(parent)));
+    // Tasks that are their own ancestors are never reached from a root,
+    // they are listed at the top level after the others
+    for todo in roots.chain(todos.iter()) {
+        push_subtree(todo, 0, false, &children, collapsed, &mut visited, &mut ordered);
+    }
+    ordered
+}
+
+fn push_subtree<'a>(
+    todo: &'a Todo,
+    depth: usize,
+    hidden: bool,
+    children: &HashMap<&str, Vec<&'a Todo>>,
+    collapsed: &HashSet<String>,
+    visited: &mut HashSet<&'a str>,
+    ordered: &mut Vec<(&'a Todo, usize)>,
+) {
+    if !visited.insert(&todo.id) {
+        return;
+    }
+    if !hidden {
+        ordered.push((todo, depth));
+    }
+    let hidden = hidden || collapsed.contains(&todo.id);
+    for child in children.get(todo.id.as_str()).into_iter().flatten() {
+        push_subtree(child, depth + 1, hidden, children, collapsed, visited, ordered);
+    }
+}
+
+// How many of the subtasks of each parent are done, and how many it has
+pub fn subtask_progress(todos: &[Todo]) -> HashMap<&str, (usize, usize)> {
+    let mut progress: HashMap<&str, (usize, usize)> = HashMap::new();
+    for todo in todos {
+        if let Some(parent) = todo.parent() {
+            let (done, total) = progress.entry(parent).or_default();
+            *done += usize::from(todo.is_done());
+            *total += 1;
+        }
+    }
+    progress
+}
+
+fn parse_priority(s: &str) -> Option<(char, &str)> {
+    let bytes = s.as_bytes();
+    if bytes.len() >= 4 && bytes[0] == b'(' && bytes[1].is_ascii_uppercase() && bytes[2] == b')' && bytes[3] == b' ' {
+        Some((bytes[1] as char, &s[4..]))
+    } else {
+        None
+    }
+}
+
+fn parse_date(s: &str) -> Option<(NaiveDate, &str)> {
+    let (word, rest) = s.split_once(' ').unwrap_or((s, ""));
+    if word.len() != 10 {
+        return None;
+    }
+    NaiveDate::parse_from_str(word, DATE_FORMAT).ok().map(|date| (date, rest))
+}
+
+fn parse_tag(word: &str) -> Option<(&str, &str)> {
+    let (key, value) = word.split_once(':')?;
+    // Keep URLs such as `https://example.com` out of the tag list.
+    if key.is_empty() || value.is_empty() || value.contains(':') || value.starts_with("//") {
+        return None;
+    }
+    Some((key, value))
+}
+
+// A priority written on its own, such as the value of a `pri:` tag
+pub(crate) fn single_priority(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_uppercase() => Some(c),
+        _ => None,
+    }
+}
+
+fn merge_imported(todos: &mut Vec<Todo>, imported: Vec<Todo>) -> Imported {
+    let mut counts = Imported::default();
+    for mut todo in imported {
+        match todos.iter_mut().find(|existing| !todo.id.is_empty() && existing.id == todo.id) {
+            Some(existing) => {
+                todo.creation_date = todo.creation_date.or(existing.creation_date);
+                // Most formats have nowhere to keep notes
+                if todo.notes.is_empty() {
+                    todo.notes = existing.notes.clone();
+                }
+                if *existing != todo {
+                    *existing = todo;
+                    counts.updated += 1;
+                }
+            },
+            None => {
+                if todo.id.is_empty() {
+                    todo.id = new_id();
+                }
+                if todo.creation_date.is_none() {
+                    todo.creation_date = Some(Local::now().date_naive());
+                }
+                todos.push(todo);
+                counts.added += 1;
+            },
+        }
+    }
+    counts
+}
+
+// Outcome of re-reading a list that changed on disk
+pub enum Reload {
+    // The file still matches what we last read or wrote
+    Unchanged,
+    Reloaded,
+    // Unsaved local edits were merged with the file, listing conflicts
+    Merged(Vec<String>),
+}
+
+// What `Todos::import` changed
+#[derive(Default)]
+pub struct Imported {
+    pub added: usize,
+    pub updated: usize,
+}
+
+impl fmt::Display for Imported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} added, {} updated", self.added, self.updated)
+    }
+}
+
+// How many tasks of the list carry a `+project` or `@context`
+pub struct TagCount {
+    pub tag: String,
+    pub open: usize,
+    pub done: usize,
+}
+
 pub struct Todos {
     pub todos: Vec<Todo>,
-    pub filesave: String,
+    // Tasks moved to the archive file, as last read by `load_archive`
+    pub archived: Vec<Todo>,
+    // Deleted tasks, as last read by `load_trash`
+    pub trashed: Vec<Todo>,
+    storage: Box<dyn Storage>,
+    journal: Journal,
+    // Rewrite the list and empty the journal once it has this many
+    // entries, 0 writes the whole list on every change
+    pub compact_after: usize,
+    // Rotating backups kept of files written next to the list
+    pub backups: usize,
+    // Complete a task once all of its subtasks are done, and reopen it
+    // when one of them is reopened
+    pub complete_parents: bool,
+    // The list as last read from or written to disk
+    base: Vec<Todo>,
+    // Set when another instance has the list open
+    read_only: bool,
+    // Git repository every save is committed to, when versioning is on
+    history: Option<History>,
 }
 
 impl Todos {
-    pub fn new(filename: &str) -> Self {
+    pub fn new(storage: Box<dyn Storage>) -> Self {
         Todos {
             todos: Vec::new(),
-            filesave: String::from(filename),
+            archived: Vec::new(),
+            trashed: Vec::new(),
+            journal: Journal::new(storage.path()),
+            storage,
+            compact_after: DEFAULT_COMPACT_AFTER,
+            backups: DEFAULT_BACKUPS,
+            complete_parents: false,
+            base: Vec::new(),
+            read_only: false,
+            history: None,
         }
     }
 
-    pub fn add_todo(&mut self, description: &str) {
-        let new_todo = Todo::new(description);
-        self.todos.push(new_todo);
-        self.save_to_file();
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
     }
 
-    pub fn remove_todo(&mut self, index: usize, done: Option<bool>) {
-        let task = match done {
-            Some(true) => self.todos.iter().filter(|todo| todo.is_done()).nth(index),
-            Some(false) => self.todos.iter().filter(|todo| !todo.is_done()).nth(index),
-            None => self.todos.get(index),
-        };
-        match task {
-            Some(task) => {
-                let index = self.todos.iter().position(|x| eq(x, task)).unwrap();
-                self.todos.remove(index);
-                self.save_to_file();
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn check_writable(&self) -> Result<(), TodoError> {
+        match self.read_only {
+            true => Err(TodoError::ReadOnly(self.path().to_path_buf())),
+            false => Ok(()),
+        }
+    }
+
+    pub fn set_history(&mut self, history: Option<History>) {
+        self.history = history;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.todos != self.base
+    }
+
+    pub fn path(&self) -> &Path {
+        self.storage.path()
+    }
+
+    pub fn add_todo(&mut self, description: &str) -> Result<(), TodoError> {
+        self.check_writable()?;
+        let description = expand_due(description, Local::now().date_naive())?;
+        let mut new_todo = Todo::new(&description);
+        self.storage.normalize(&mut new_todo);
+        self.todos.push(new_todo.clone());
+        self.record(Operation::Add(new_todo))
+    }
+
+    pub fn add_subtask(&mut self, parent: &str, description: &str) -> Result<(), TodoError> {
+        if !self.todos.iter().any(|todo| todo.id == parent) {
+            return Err(TodoError::NotFound(format!("task {}", parent)));
+        }
+        self.add_todo(&format!("{} {}:{}", description.trim(), PARENT_TAG, parent))
+    }
+
+    // Adds tasks read from another format, saved in one go. A task whose id
+    // is already in the list replaces it, so importing the same file twice
+    // doesn't duplicate anything.
+    pub fn import(&mut self, imported: Vec<Todo>, source: &str) -> Result<Imported, TodoError> {
+        self.check_writable()?;
+        let imported = self.normalized(imported);
+        let counts = merge_imported(&mut self.todos, imported);
+        if counts.added + counts.updated > 0 {
+            self.save(&format!("import from {}: {}", source, counts))?;
+        }
+        Ok(counts)
+    }
+
+    // What `import` would do, without changing anything
+    pub fn preview_import(&self, imported: &[Todo]) -> Imported {
+        merge_imported(&mut self.todos.clone(), self.normalized(imported.to_vec()))
+    }
+
+    fn normalized(&self, mut todos: Vec<Todo>) -> Vec<Todo> {
+        for todo in todos.iter_mut() {
+            self.storage.normalize(todo);
+        }
+        todos
+    }
+
+    pub fn edit_todo(&mut self, id: &str, description: &str) -> Result<(), TodoError> {
+        self.check_writable()?;
+        let description = expand_due(description, Local::now().date_naive())?;
+        match self.get_task(id) {
+            Some(todo) => {
+                // A subtask stays under its parent when its text is retyped
+                let parent = todo.parent().map(String::from);
+                todo.set_description(&description);
+                if let (Some(parent), None) = (parent, todo.parent()) {
+                    todo.set_tag(PARENT_TAG, Some(&parent));
+                }
+                let todo = todo.clone();
+                self.normalize_task(todo, Operation::Edit)
             },
-            None => (),
+            None => Err(TodoError::NotFound(format!("task {}", id))),
         }
     }
 
-    pub fn save_to_file(&self) {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(&self.filesave)
-            .unwrap();
+    // Moves a task to the trash, stamped with the time it was deleted. Like
+    // `archive`, the trash is written before the list.
+    pub fn remove_todo(&mut self, id: &str) -> Result<(), TodoError> {
+        self.check_writable()?;
+        let index = self.todos.iter().position(|todo| todo.id == id).ok_or_else(|| TodoError::NotFound(format!("task {}", id)))?;
+        let mut deleted = self.todos[index].clone();
+        deleted.set_tag(DELETED_TAG, Some(&Utc::now().format(DELETED_FORMAT).to_string()));
+        let mut storage = self.trash_storage();
+        {
+            let _lock = FileLock::exclusive(storage.path())?;
+            let mut trashed = self.read_companion(&mut *storage)?;
+            trashed.push(deleted);
+            storage.save(&trashed)?;
+            self.trashed = trashed;
+        }
+        self.todos.remove(index);
+        self.record(Operation::Delete(id.to_string()))
+    }
 
-        for todo in &self.todos {
-            let status = if todo.is_done() { "x" } else { " " };
-            writeln!(file, "{},{}", status, todo.description).unwrap();
+    pub fn toggle_todo(&mut self, id: &str) -> Result<(), TodoError> {
+        self.check_writable()?;
+        match self.get_task(id) {
+            Some(todo) => {
+                match todo.is_done() {
+                    true => todo.mark_as_undone(),
+                    false => todo.mark_as_done(),
+                };
+                let todo = todo.clone();
+                let parent = todo.parent().map(String::from);
+                self.normalize_task(todo, Operation::Toggle)?;
+                match (self.complete_parents, parent) {
+                    (true, Some(parent)) => self.update_parents(&parent),
+                    _ => Ok(()),
+                }
+            },
+            None => Err(TodoError::NotFound(format!("task {}", id))),
         }
     }
 
-    pub fn get_task(&mut self, index: usize, done: Option<bool>) -> Option<&mut Todo> {
-        match done {
-            Some(true) => self.todos.iter_mut().filter(|todo| todo.is_done()).nth(index),
-            Some(false) => self.todos.iter_mut().filter(|todo| !todo.is_done()).nth(index),
-            None => self.todos.get_mut(index),
+    // Completes a parent whose subtasks are all done, or reopens it, and
+    // then its own parent
+    fn update_parents(&mut self, id: &str) -> Result<(), TodoError> {
+        let mut next = Some(id.to_string());
+        while let Some(id) = next {
+            let Some((done, total)) = subtask_progress(&self.todos).get(id.as_str()).copied() else {
+                break;
+            };
+            let Some(parent) = self.get_task(&id).filter(|parent| parent.is_done() != (done == total)) else {
+                break;
+            };
+            match done == total {
+                true => parent.mark_as_done(),
+                false => parent.mark_as_undone(),
+            }
+            let parent = parent.clone();
+            next = parent.parent().map(String::from);
+            self.normalize_task(parent, Operation::Toggle)?;
+        }
+        Ok(())
+    }
+
+    // `+` on a task: B becomes A, a task without a priority gets A
+    pub fn raise_priority(&mut self, id: &str) -> Result<(), TodoError> {
+        self.change_priority(id, |priority| match priority {
+            Some(p) if p > 'A' => Some((p as u8 - 1) as char),
+            _ => Some('A'),
+        })
+    }
+
+    // `-` on a task: A becomes B, and Z drops the priority
+    pub fn lower_priority(&mut self, id: &str) -> Result<(), TodoError> {
+        self.change_priority(id, |priority| match priority {
+            Some(p) if p < 'Z' => Some((p as u8 + 1) as char),
+            _ => None,
+        })
+    }
+
+    pub fn set_notes(&mut self, id: &str, notes: &str) -> Result<(), TodoError> {
+        self.check_writable()?;
+        match self.get_task(id) {
+            Some(todo) => {
+                todo.notes = clean_notes(notes);
+                let todo = todo.clone();
+                self.normalize_task(todo, Operation::Edit)
+            },
+            None => Err(TodoError::NotFound(format!("task {}", id))),
+        }
+    }
+
+    fn change_priority(&mut self, id: &str, change: fn(Option<char>) -> Option<char>) -> Result<(), TodoError> {
+        self.check_writable()?;
+        match self.get_task(id) {
+            Some(todo) if todo.is_done() => Err(TodoError::Unsupported(String::from("done tasks have no priority, reopen the task first"))),
+            Some(todo) => {
+                todo.priority = change(todo.priority);
+                let todo = todo.clone();
+                self.normalize_task(todo, Operation::Edit)
+            },
+            None => Err(TodoError::NotFound(format!("task {}", id))),
+        }
+    }
+
+    // Records an edit of a task, as the storage will read it back
+    fn normalize_task(&mut self, mut todo: Todo, operation: fn(Todo) -> Operation) -> Result<(), TodoError> {
+        self.storage.normalize(&mut todo);
+        if let Some(task) = self.todos.iter_mut().find(|task| task.id == todo.id) {
+            *task = todo.clone();
+        }
+        self.record(operation(todo))
+    }
+
+    // Persists a change already applied to `todos`
+    fn record(&mut self, operation: Operation) -> Result<(), TodoError> {
+        // The journal is plain text, encrypted lists are saved whole instead.
+        // Versioned lists are too, so that every change is a commit.
+        if self.compact_after == 0 || self.is_encrypted() || self.history.is_some() {
+            let message = self.describe(&operation);
+            let before = self.base.iter().find(|todo| todo.id == operation.id()).cloned();
+            self.save(&message)?;
+            // Still kept in the audit trail, unless it would give away an
+            // encrypted list
+            if !self.is_encrypted() {
+                self.journal.log(&operation, before.as_ref())?;
+            }
+            return Ok(());
+        }
+        {
+            let _lock = FileLock::exclusive(self.path())?;
+            let before = self.base.iter().find(|todo| todo.id == operation.id());
+            self.journal.append(&operation, before)?;
+        }
+        self.base = self.todos.clone();
+        if self.journal.len() >= self.compact_after {
+            self.save_to_file()?;
+        }
+        Ok(())
+    }
+
+    // Commit message for a change, e.g. "complete: Feed the cat"
+    fn describe(&self, operation: &Operation) -> String {
+        match operation {
+            Operation::Add(todo) => format!("add: {}", todo.description),
+            Operation::Toggle(todo) if todo.is_done() => format!("complete: {}", todo.description),
+            Operation::Toggle(todo) => format!("reopen: {}", todo.description),
+            Operation::Edit(todo) => format!("edit: {}", todo.description),
+            Operation::Delete(id) => match self.base.iter().find(|todo| todo.id == *id) {
+                Some(todo) => format!("delete: {}", todo.description),
+                None => format!("delete: {}", id),
+            },
+        }
+    }
+
+    pub fn save_to_file(&mut self) -> Result<(), TodoError> {
+        self.save("save")
+    }
+
+    // Folds pending journal entries into the list so that other programs
+    // reading the file see them
+    pub fn flush(&mut self) -> Result<(), TodoError> {
+        if self.journal.len() == 0 || self.read_only {
+            return Ok(());
+        }
+        self.save_to_file()
+    }
+
+    // Writes the whole list, compacts the journal and commits the list when
+    // it is versioned
+    fn save(&mut self, message: &str) -> Result<(), TodoError> {
+        self.check_writable()?;
+        let _lock = FileLock::exclusive(self.path())?;
+        self.storage.save(&self.todos)?;
+        self.journal.compact()?;
+        self.base = self.todos.clone();
+        if let Some(history) = &self.history {
+            history.commit(self.path(), message)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.storage.is_encrypted()
+    }
+
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), TodoError> {
+        self.storage.unlock(passphrase)
+    }
+
+    // Rewrites the list encrypted under `passphrase`, or as a plain `backend`
+    // list when `None`. The backups and the journal either hold the old
+    // contents in the clear or can't be read with the new key, so they go.
+    pub fn set_encryption(&mut self, passphrase: Option<&str>, backend: Backend, backups: usize) -> Result<(), TodoError> {
+        self.check_writable()?;
+        if backend == Backend::Sqlite {
+            return Err(TodoError::Unsupported(String::from("SQLite lists can't be encrypted")));
+        }
+        self.save_to_file()?;
+        let path = self.path().to_path_buf();
+        // The archive and the trash are read in the old format and written
+        // in the new one along with the list
+        let mut locks = vec![];
+        let mut companions = vec![];
+        for mut companion in [self.archive_storage(), self.trash_storage()] {
+            if !companion.path().exists() {
+                continue;
+            }
+            locks.push(FileLock::exclusive(companion.path())?);
+            let todos = self.read_companion(&mut *companion)?;
+            companions.push((companion.path().to_path_buf(), todos));
+        }
+        self.storage = match passphrase {
+            Some(passphrase) => Box::new(EncryptedStorage::with_passphrase(&path, backups, passphrase)),
+            None => storage::open(&path, backend, backups),
+        };
+        self.save(if passphrase.is_some() { "encrypt" } else { "decrypt" })?;
+        storage::remove_backups(&path)?;
+        for (companion, todos) in companions {
+            self.storage.companion(&companion, self.backups).save(&todos)?;
+            storage::remove_backups(&companion)?;
+        }
+        self.journal.remove()
+    }
+
+    // Reads the list and replays the journal on top of it
+    fn read(&mut self) -> Result<(Loaded, Replayed), TodoError> {
+        let _lock = FileLock::shared(self.path())?;
+        let mut loaded = self.storage.load()?;
+        let (todos, replayed) = self.journal.replay(&loaded.todos)?;
+        loaded.todos = todos;
+        Ok((loaded, replayed))
+    }
+
+    // Gives tasks written by other tools an id. The list is saved right away
+    // so that journal entries can refer to them.
+    fn assign_ids(&mut self) -> Result<(), TodoError> {
+        let mut assigned = false;
+        for todo in self.todos.iter_mut().filter(|todo| todo.id.is_empty()) {
+            todo.id = new_id();
+            assigned = true;
+        }
+        if (assigned || self.storage.made_up_ids()) && !self.read_only {
+            self.save("assign ids")?;
+        }
+        Ok(())
+    }
+
+    // Picks up changes made to the file by other programs, merging them
+    // with any edits that couldn't be saved yet.
+    pub fn reload(&mut self) -> Result<Reload, TodoError> {
+        let (loaded, replayed) = self.read()?;
+        let theirs = loaded.todos;
+        if theirs == self.base {
+            return Ok(Reload::Unchanged);
+        }
+        self.commit_outside_changes()?;
+        if !self.is_dirty() {
+            self.todos = theirs.clone();
+            self.base = theirs;
+            self.assign_ids()?;
+            // Another program is using the file, hand it our entries too
+            self.flush()?;
+            return match replayed.conflicts.is_empty() {
+                true => Ok(Reload::Reloaded),
+                false => Ok(Reload::Merged(replayed.conflicts)),
+            };
+        }
+
+        let (merged, mut conflicts) = merge(&self.base, &self.todos, &theirs);
+        conflicts.extend(replayed.conflicts);
+        self.todos = merged;
+        self.base = theirs;
+        for todo in self.todos.iter_mut().filter(|todo| todo.id.is_empty()) {
+            todo.id = new_id();
+        }
+        self.save("merge changes made on disk")?;
+        Ok(Reload::Merged(conflicts))
+    }
+
+    // Rolls the list back to its n-th backup. The state being replaced
+    // becomes the newest backup, so restoring `.bak.1` twice is a no-op.
+    pub fn restore_backup(&mut self, n: usize) -> Result<Vec<TodoError>, TodoError> {
+        self.check_writable()?;
+        let loaded = {
+            let _lock = FileLock::shared(self.path())?;
+            self.storage.load_backup(n)?
+        };
+        self.replace_with(loaded, &format!("restore backup {}", n))
+    }
+
+    // Commits what other programs wrote to the file, so that it isn't
+    // mixed into the commit of our next change
+    fn commit_outside_changes(&self) -> Result<(), TodoError> {
+        match (&self.history, self.read_only) {
+            (Some(history), false) => history.commit(self.path(), "changes made outside todo-rs"),
+            _ => Ok(()),
+        }
+    }
+
+    fn archive_storage(&self) -> Box<dyn Storage> {
+        self.storage.companion(&archive_path(self.path()), self.backups)
+    }
+
+    // Moves every done task to the archive file, `done.txt` for `todo.txt`.
+    // The archive is written first, a crash in between leaves a task in
+    // both files rather than in neither.
+    pub fn archive(&mut self) -> Result<usize, TodoError> {
+        self.check_writable()?;
+        let (done, open): (Vec<Todo>, Vec<Todo>) = self.todos.iter().cloned().partition(|todo| todo.is_done());
+        if done.is_empty() {
+            return Ok(0);
+        }
+        let mut storage = self.archive_storage();
+        {
+            let _lock = FileLock::exclusive(storage.path())?;
+            let mut archived = self.read_companion(&mut *storage)?;
+            for mut todo in done.iter().cloned() {
+                todo.completion_date = todo.completion_date.or_else(|| Some(Local::now().date_naive()));
+                archived.push(todo);
+            }
+            storage.save(&archived)?;
+            self.archived = archived;
+        }
+        self.todos = open;
+        self.save(&format!("archive {} done task(s)", done.len()))?;
+        Ok(done.len())
+    }
+
+    pub fn load_archive(&mut self) -> Result<(), TodoError> {
+        let mut storage = self.archive_storage();
+        let _lock = FileLock::exclusive(storage.path())?;
+        self.archived = self.read_companion(&mut *storage)?;
+        Ok(())
+    }
+
+    // Moves an archived task back into the list
+    pub fn unarchive(&mut self, id: &str) -> Result<(), TodoError> {
+        self.archived = self.restore_from(self.archive_storage(), id)?;
+        Ok(())
+    }
+
+    fn trash_storage(&self) -> Box<dyn Storage> {
+        self.storage.companion(&trash_path(self.path()), self.backups)
+    }
+
+    pub fn load_trash(&mut self) -> Result<(), TodoError> {
+        let mut storage = self.trash_storage();
+        let _lock = FileLock::exclusive(storage.path())?;
+        self.trashed = self.read_companion(&mut *storage)?;
+        Ok(())
+    }
+
+    // Moves a deleted task back into the list
+    pub fn restore_deleted(&mut self, id: &str) -> Result<(), TodoError> {
+        self.trashed = self.restore_from(self.trash_storage(), id)?;
+        Ok(())
+    }
+
+    // Deletes a task from the trash for good
+    pub fn purge(&mut self, id: &str) -> Result<(), TodoError> {
+        self.purge_where(|todo| todo.id == id)?;
+        Ok(())
+    }
+
+    // Purges tasks deleted more than `days` days ago, returns how many
+    pub fn empty_trash(&mut self, days: u32) -> Result<usize, TodoError> {
+        let cutoff = Utc::now() - Duration::days(days.into());
+        // Tasks without a readable timestamp are kept
+        self.purge_where(|todo| todo.deleted_at().is_some_and(|deleted| deleted < cutoff))
+    }
+
+    fn purge_where(&mut self, purge: impl Fn(&Todo) -> bool) -> Result<usize, TodoError> {
+        self.check_writable()?;
+        let mut storage = self.trash_storage();
+        let _lock = FileLock::exclusive(storage.path())?;
+        let mut trashed = self.read_companion(&mut *storage)?;
+        let before = trashed.len();
+        trashed.retain(|todo| !purge(todo));
+        let purged = before - trashed.len();
+        if purged > 0 {
+            storage.save(&trashed)?;
+        }
+        self.trashed = trashed;
+        Ok(purged)
+    }
+
+    // Reads the archive or the trash, the caller holding its lock. Tasks
+    // written there by other tools get an id to be restored by. A file that
+    // can't be read in full is refused, saving it would drop what wasn't
+    // understood.
+    fn read_companion(&self, storage: &mut dyn Storage) -> Result<Vec<Todo>, TodoError> {
+        let path = storage.path().display().to_string();
+        if !storage.is_encrypted() && storage::is_encrypted_file(storage.path()) {
+            return Err(TodoError::Unsupported(format!("{} is encrypted but the list isn't", path)));
+        }
+        let loaded = storage.load()?;
+        if let Some(first) = loaded.malformed.first() {
+            return Err(TodoError::Unsupported(format!("{} has {} malformed line(s), left untouched: {}", path, loaded.malformed.len(), first)));
+        }
+        let mut todos = loaded.todos;
+        if todos.iter().any(|todo| todo.id.is_empty()) && !self.read_only {
+            for todo in todos.iter_mut().filter(|todo| todo.id.is_empty()) {
+                todo.id = new_id();
+            }
+            storage.save(&todos)?;
+        }
+        Ok(todos)
+    }
+
+    // Takes a task out of the archive or the trash and adds it back to the
+    // list. Returns what is left there.
+    fn restore_from(&mut self, mut storage: Box<dyn Storage>, id: &str) -> Result<Vec<Todo>, TodoError> {
+        self.check_writable()?;
+        let _lock = FileLock::exclusive(storage.path())?;
+        let mut todos = self.read_companion(&mut *storage)?;
+        let index = todos.iter().position(|todo| todo.id == id)
+            .ok_or_else(|| TodoError::NotFound(format!("task {} in {}", id, storage.path().display())))?;
+        let mut todo = todos.remove(index);
+        todo.set_tag(DELETED_TAG, None);
+        self.storage.normalize(&mut todo);
+        self.todos.push(todo.clone());
+        self.record(Operation::Add(todo))?;
+        storage.save(&todos)?;
+        Ok(todos)
+    }
+
+    // Versions of the list in its history, newest first
+    pub fn versions(&self) -> Result<Vec<Version>, TodoError> {
+        match &self.history {
+            Some(history) => history.versions(self.path()),
+            None => Ok(vec![]),
+        }
+    }
+
+    // Rolls the list back to a version from its history, as a new commit
+    pub fn restore_version(&mut self, id: Oid) -> Result<Vec<TodoError>, TodoError> {
+        self.check_writable()?;
+        let history = self.history.as_ref().ok_or_else(|| TodoError::NotFound(String::from("history")))?;
+        let contents = history.contents(self.path(), id)?;
+        let loaded = self.storage.load_bytes(&contents)?;
+        self.replace_with(loaded, &format!("restore: version {:.7}", id))
+    }
+
+    fn replace_with(&mut self, loaded: Loaded, message: &str) -> Result<Vec<TodoError>, TodoError> {
+        self.todos = loaded.todos;
+        for todo in self.todos.iter_mut().filter(|todo| todo.id.is_empty()) {
+            todo.id = new_id();
         }
+        self.save(message)?;
+        Ok(loaded.malformed)
+    }
+
+    pub fn get_task(&mut self, id: &str) -> Option<&mut Todo> {
+        self.todos.iter_mut().find(|todo| todo.id == id)
     }
 
     pub fn get_tasks(&self, done: Option<bool>) -> Vec<&Todo> {
@@ -91,36 +988,43 @@ impl Todos {
             None => self.todos.iter().collect::<Vec<_>>(),
         }
     }
-    pub fn get_number_of_tasks(&self, done: Option<bool>) -> usize {
-        match done {
-            Some(true) => self.todos.iter().filter(|todo| todo.is_done()).count(),
-            Some(false) => self.todos.iter().filter(|todo| !todo.is_done()).count(),
-            None => self.todos.len(),
-        }
-    }
-
-    pub fn load_from_file(&mut self, filename: &str) {
-        let file = File::open(filename);
-        match file {
-            Ok(f) => {
-                let reader = BufReader::new(f);
-                let mut todos: Vec<Todo> = vec![];
-                for line in reader.lines() {
-                    if let Ok(line) = line {
-                        let splitted = line.split(",").collect::<Vec<&str>>();
-                        todos.push(
-                            Todo {
-                                description: splitted[1].to_string(),
-                                done: if splitted[0] == "x" { true } else { false },
-                            }
-                        );
-                    }
+
+    // Every project, then every context, of the list's tasks
+    pub fn tag_counts(&self) -> Vec<TagCount> {
+        let mut counts: Vec<TagCount> = vec![];
+        for todo in &self.todos {
+            let projects = todo.projects.iter().map(|project| format!("+{}", project));
+            let contexts = todo.contexts.iter().map(|context| format!("@{}", context));
+            let mut tags = projects.chain(contexts).collect::<Vec<_>>();
+            tags.sort();
+            tags.dedup();
+            for tag in tags {
+                let index = match counts.iter().position(|count| count.tag == tag) {
+                    Some(index) => index,
+                    None => {
+                        counts.push(TagCount { tag, open: 0, done: 0 });
+                        counts.len() - 1
+                    },
+                };
+                match todo.is_done() {
+                    true => counts[index].done += 1,
+                    false => counts[index].open += 1,
                 }
-                self.todos = todos;
-                self.filesave = filename.to_string();
-            },
-            Err(e) => eprintln!("Error: {}", e)
+            }
         }
+        counts.sort_by_key(|count| (!count.tag.starts_with('+'), count.tag.to_lowercase()));
+        counts
+    }
+
+    pub fn load_from_file(&mut self) -> Result<Vec<TodoError>, TodoError> {
+        let (loaded, replayed) = self.read()?;
+        self.commit_outside_changes()?;
+        self.todos = loaded.todos;
+        self.base = self.todos.clone();
+        self.assign_ids()?;
+        let mut malformed = loaded.malformed;
+        malformed.extend(replayed.malformed);
+        Ok(malformed)
     }
     // pub fn list_all_tasks(&self) {
     //     self.print_task_list(None);
@@ -175,3 +1079,82 @@ impl Todos {
 
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(y, m, d)
+    }
+
+    fn round_trip(line: &str) -> Todo {
+        let todo = Todo::parse(line);
+        assert_eq!(todo.to_string(), line);
+        todo
+    }
+
+    #[test]
+    fn done_tasks_with_both_dates() {
+        let todo = round_trip("x (A) 2023-05-02 2023-05-01 Call mom +family @phone due:2023-05-03");
+        assert!(todo.done);
+        assert_eq!(todo.priority, Some('A'));
+        assert_eq!(todo.completion_date, date(2023, 5, 2));
+        assert_eq!(todo.creation_date, date(2023, 5, 1));
+        assert_eq!(todo.description, "Call mom +family @phone due:2023-05-03");
+        assert_eq!(todo.projects, ["family"]);
+        assert_eq!(todo.contexts, ["phone"]);
+        assert_eq!(todo.due_date(), date(2023, 5, 3));
+    }
+
+    #[test]
+    fn open_tasks_have_a_creation_date_only() {
+        let todo = round_trip("(B) 2023-05-01 Pay rent");
+        assert!(!todo.done);
+        assert_eq!(todo.creation_date, date(2023, 5, 1));
+        assert_eq!(todo.completion_date, None);
+        assert_eq!(todo.description, "Pay rent");
+    }
+
+    #[test]
+    fn urls_are_not_tags() {
+        let todo = round_trip("Read https://example.com/a:b and mailto:me@example.com later key:value");
+        assert_eq!(todo.tags, [(String::from("mailto"), String::from("me@example.com")), (String::from("key"), String::from("value"))]);
+        assert_eq!(todo.contexts, Vec::<String>::new());
+    }
+
+    #[test]
+    fn the_id_is_kept_out_of_the_description() {
+        let todo = Todo::parse("Feed id:01H0ZKXJ6V4Q8Y1ZB1W0Q5N2X3 the cat");
+        assert_eq!(todo.id, "01H0ZKXJ6V4Q8Y1ZB1W0Q5N2X3");
+        assert_eq!(todo.description, "Feed the cat");
+        assert_eq!(todo.to_string(), "Feed the cat id:01H0ZKXJ6V4Q8Y1ZB1W0Q5N2X3");
+        round_trip("Feed the cat id:01H0ZKXJ6V4Q8Y1ZB1W0Q5N2X3");
+    }
+
+    #[test]
+    fn notes_follow_the_first_line_break() {
+        let todo = round_trip("Fix the bug id:bug\nSteps:\n  1. open it");
+        assert_eq!(todo.description, "Fix the bug");
+        assert_eq!(todo.notes, "Steps:\n  1. open it");
+    }
+
+    #[test]
+    fn done_tasks_without_a_completion_date_keep_their_creation_date() {
+        // As read from a legacy `x,2023-01-01 Feed the cat` line
+        let todo = Todo {
+            done: true,
+            ..Todo::parse("2023-01-01 Feed the cat")
+        };
+        let reloaded = Todo::parse(&todo.to_string());
+        assert!(reloaded.done);
+        assert_eq!(reloaded.creation_date, date(2023, 1, 1));
+        assert_eq!(reloaded.description, "Feed the cat");
+    }
+
+    #[test]
+    fn priorities_need_parentheses_and_a_space() {
+        assert_eq!(Todo::parse("(a) lower case").priority, None);
+        assert_eq!(Todo::parse("(A)no space").priority, None);
+        assert_eq!(Todo::parse("x (Z) done").priority, Some('Z'));
+    }
+}
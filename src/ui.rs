@@ -1,38 +1,121 @@
+use chrono::Local;
 use crossterm::{
     cursor,
-    event::{self, Event, KeyCode, KeyEvent},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     execute,
     style::{Color, Print, ResetColor, SetForegroundColor, SetBackgroundColor},
     terminal::{self, Clear, ClearType},
     Result,
 };
+use std::collections::{HashMap, HashSet};
 use std::io::{self, Write};
+use std::time::Duration;
+use zeroize::Zeroizing;
 
-use crate::todo::Todos;
+use crate::dates::relative;
+use crate::todo::{subtask_progress, tree_order, TagCount, Todo, Todos, DATE_FORMAT, PARENT_TAG};
 
 
 const DESCRIPTION_WIDTH: usize = 30;
+const DUE_WIDTH: usize = 11;
 const STATUS_WIDTH: usize = 10;
 const INDEX_WIDTH: usize = 3;
 
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+const LIST_ROW: u16 = 0;
 const MODE_ROW: u16 = 1;
 const HEADER_ROW: u16 = 3;
 
+// 256-colour palette entries for G to Z, going round the colour wheel from
+// blue so that neighbouring priorities can still be told apart
+const LOW_PRIORITY_COLORS: [u8; 20] = [33, 39, 45, 51, 49, 48, 84, 120, 156, 192, 228, 222, 216, 210, 204, 198, 199, 135, 141, 147];
+
 pub struct TodoUI {
     active_screen: TodoTab,
     cursor_row: usize,
+    message: Option<Message>,
+    list_name: String,
+    // Only tasks whose description contains this are shown, set with `/`
+    search: Option<String>,
+    // Only tasks with this `+project` or `@context` are shown, picked with `p`
+    tag_filter: Option<String>,
+    // Every tab lists the most important tasks first, toggled with `s`
+    sort_by_priority: bool,
+    // Tasks whose subtasks are hidden, toggled with `c`
+    collapsed: HashSet<String>,
+    // Show the selected task in full with its notes, toggled with `v`
+    show_details: bool,
+    list_picker: Option<ListPicker>,
+    tag_picker: Option<TagPicker>,
+    history: Option<HistoryView>,
+}
+
+// Overlay listing every list, opened with `L`
+struct ListPicker {
+    names: Vec<String>,
+    cursor_row: usize,
+}
+
+// Projects and contexts with their task counts, opened with `p`. The first
+// row shows every task again.
+struct TagPicker {
+    tags: Vec<TagCount>,
+    cursor_row: usize,
 }
 
+// Past versions of the list, opened with `H`
+struct HistoryView {
+    // Commit id and description of each version, newest first
+    versions: Vec<(String, String)>,
+    cursor_row: usize,
+}
+
+// Shown under the list until the next key press
+pub enum Message {
+    Info(String),
+    Error(String),
+}
+
+#[allow(clippy::enum_variant_names)]
 pub enum TodoTab {
     TodoList,
     DoneList,
     UndoneList,
+    // Tasks moved to the archive file, read-only except for restoring
+    ArchiveList,
+    // Deleted tasks, to be restored or purged
+    TrashList,
 }
 
 pub enum UiEvent {
     Quit,
     AddTodo,
+    EditTodo(String),
+    AddSubtask(String),
+    EditNotes(String),
+    EditNotesInEditor(String),
+    RemoveTodo(String),
+    RestoreDeleted(String),
+    PurgeTodo(String),
+    ToggleTodo(String),
+    RaisePriority(String),
+    LowerPriority(String),
     ChangeTab(TodoTab),
+    OpenListPicker,
+    SwitchList(String),
+    CreateList,
+    RenameList(String),
+    DeleteList(String),
+    RestoreBackup,
+    ChangeEncryption,
+    OpenHistory,
+    RestoreVersion(String),
+    Archive,
+    Unarchive(String),
+    Import,
+    Export,
+    Search,
     RefreshUI,
 }
 
@@ -51,6 +134,16 @@ impl Display for TodoUI {
         Self {
             active_screen: TodoTab::TodoList,
             cursor_row: 0,
+            message: None,
+            list_name: String::new(),
+            search: None,
+            sort_by_priority: false,
+            collapsed: HashSet::new(),
+            show_details: false,
+            tag_filter: None,
+            list_picker: None,
+            tag_picker: None,
+            history: None,
         }
     }
 }
@@ -59,22 +152,77 @@ impl TodoUI {
     pub fn render(&self, todos: &Todos) -> Result<()> {
         let mut stdout = io::stdout();
         // Render the header
-        let header = format!("{:^width$}", " All  Done  Undone ", width=DESCRIPTION_WIDTH+STATUS_WIDTH+INDEX_WIDTH+3);
+        let header = format!("{:^width$}", " All  Done  Undone  Archive  Trash ", width=DESCRIPTION_WIDTH+DUE_WIDTH+STATUS_WIDTH+INDEX_WIDTH+6);
         execute!(
             stdout,
             Clear(ClearType::All),
-            cursor::MoveTo(0, MODE_ROW as u16),
+            cursor::MoveTo(0, LIST_ROW),
+            SetForegroundColor(Color::Green),
+            Print(format!("List: {}", self.list_name)),
+            Print(if todos.is_encrypted() { " [encrypted]" } else { "" }),
+            SetForegroundColor(Color::Yellow),
+            Print(self.tag_filter.as_ref().map(|tag| format!("  {}", tag)).unwrap_or_default()),
+            Print(self.search.as_ref().map(|search| format!("  search: \"{}\"", search)).unwrap_or_default()),
+            Print(if self.sort_by_priority { "  sorted by priority" } else { "" }),
+            cursor::MoveTo(0, MODE_ROW),
             SetForegroundColor(Color::White),
             Print(&header),
             ResetColor,
         )?;
+        let start_of_trash = header.find("Trash").unwrap()-1;
+        if todos.is_read_only() {
+            execute!(
+                stdout,
+                cursor::MoveTo((start_of_trash + "[Trash] ".len()) as u16, MODE_ROW),
+                SetForegroundColor(Color::Red),
+                Print("[read-only]"),
+                ResetColor,
+            )?;
+        }
+
+        // Render the todos, or a picker or the history on top of them
+        match (&self.list_picker, &self.tag_picker, &self.history) {
+            (Some(picker), _, _) => self.render_list_picker(&mut stdout, picker)?,
+            (None, Some(picker), _) => self.render_tag_picker(&mut stdout, picker)?,
+            (None, None, Some(history)) => self.render_history(&mut stdout, history)?,
+            (None, None, None) => self.render_tab(&mut stdout, todos, &header)?,
+        }
+
+        // Render the footer
+        execute!(stdout, ResetColor)?;
+        if let Some(message) = &self.message {
+            let (color, text) = match message {
+                Message::Info(text) => (Color::Yellow, text),
+                Message::Error(text) => (Color::Red, text),
+            };
+            execute!(
+                stdout,
+                cursor::MoveToNextLine(1),
+                SetForegroundColor(color),
+                Print(text),
+                ResetColor,
+            )?;
+        }
+        // self.render_prompt(&mut stdout)?;
+
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn render_tab(&self, stdout: &mut io::Stdout, todos: &Todos, header: &str) -> Result<()> {
         let start_of_all = header.find("All").unwrap()-1;
         let start_of_done = header.find("Done").unwrap()-1;
         let start_of_undone = header.find("Undone").unwrap()-1;
+        let start_of_archive = header.find("Archive").unwrap()-1;
+        let start_of_trash = header.find("Trash").unwrap()-1;
 
         // Render the todos
-        let format_header = format!("{:>INDEX_WIDTH$} | {:>DESCRIPTION_WIDTH$} | {:^STATUS_WIDTH$}","".repeat(INDEX_WIDTH),  "description", "status");
-        let format_seperator = format!("{:>INDEX_WIDTH$}-|-{:>DESCRIPTION_WIDTH$}-|-{:^STATUS_WIDTH$}","-".repeat(INDEX_WIDTH),  "-".repeat(DESCRIPTION_WIDTH), "-".repeat(STATUS_WIDTH));
+        let status = match self.active_screen {
+            TodoTab::TrashList => "deleted",
+            _ => "status",
+        };
+        let format_header = format!("{:>INDEX_WIDTH$} | {:<DESCRIPTION_WIDTH$} | {:^DUE_WIDTH$} | {:^STATUS_WIDTH$}","".repeat(INDEX_WIDTH),  "description", "due", status);
+        let format_seperator = format!("{:>INDEX_WIDTH$}-|-{:>DESCRIPTION_WIDTH$}-|-{:^DUE_WIDTH$}-|-{:^STATUS_WIDTH$}","-".repeat(INDEX_WIDTH),  "-".repeat(DESCRIPTION_WIDTH), "-".repeat(DUE_WIDTH), "-".repeat(STATUS_WIDTH));
         // let execute_header = |""
         match self.active_screen {
             TodoTab::TodoList => {
@@ -90,7 +238,7 @@ impl TodoUI {
                     Print(format_seperator),
                     cursor::MoveToNextLine(1),
                 )?;
-                self.render_todos(&mut stdout, todos, None)?;
+                self.render_todos(stdout, &self.visible_tasks(todos), self.collection(todos))?;
             },
             TodoTab::DoneList => {
                 execute!(
@@ -105,7 +253,7 @@ impl TodoUI {
                     Print(format_seperator),
                     cursor::MoveToNextLine(1),
                 )?;
-                self.render_todos(&mut stdout, todos, Some(true))?;
+                self.render_todos(stdout, &self.visible_tasks(todos), self.collection(todos))?;
             },
             TodoTab::UndoneList => {
                 execute!(
@@ -120,15 +268,168 @@ impl TodoUI {
                     Print(format_seperator),
                     cursor::MoveToNextLine(1),
                 )?;
-                self.render_todos(&mut stdout, todos, Some(false))?;
+                self.render_todos(stdout, &self.visible_tasks(todos), self.collection(todos))?;
+            },
+            TodoTab::ArchiveList => {
+                execute!(
+                    stdout,
+                    cursor::MoveTo(start_of_archive as u16, MODE_ROW),
+                    SetForegroundColor(Color::Magenta),
+                    Print("[Archive]"),
+                    cursor::MoveTo(0, HEADER_ROW),
+                    ResetColor,
+                    Print(format_header),
+                    cursor::MoveToNextLine(1),
+                    Print(format_seperator),
+                    cursor::MoveToNextLine(1),
+                )?;
+                self.render_todos(stdout, &self.visible_tasks(todos), self.collection(todos))?;
+            },
+            TodoTab::TrashList => {
+                execute!(
+                    stdout,
+                    cursor::MoveTo(start_of_trash as u16, MODE_ROW),
+                    SetForegroundColor(Color::DarkGrey),
+                    Print("[Trash]"),
+                    cursor::MoveTo(0, HEADER_ROW),
+                    ResetColor,
+                    Print(format_header),
+                    cursor::MoveToNextLine(1),
+                    Print(format_seperator),
+                    cursor::MoveToNextLine(1),
+                )?;
+                self.render_todos(stdout, &self.visible_tasks(todos), self.collection(todos))?;
             },
         }
+        if self.show_details {
+            if let Some(todo) = self.visible_tasks(todos).get(self.cursor_row) {
+                self.render_details(stdout, todo, self.collection(todos))?;
+            }
+        }
+        Ok(())
+    }
 
-        // Render the footer
-        execute!(stdout, ResetColor).unwrap();
-        // self.render_prompt(&mut stdout)?;
+    // The selected task in full under the list, notes included
+    fn render_details(&self, stdout: &mut io::Stdout, todo: &Todo, collection: &[Todo]) -> Result<()> {
+        let mut shown = todo.clone();
+        shown.set_tag(PARENT_TAG, None);
+        let mut lines = vec![match todo.priority {
+            Some(priority) => format!("({}) {}", priority, shown.description),
+            None => shown.description.clone(),
+        }];
+        let today = Local::now().date_naive();
+        let mut dates = vec![];
+        if let Some(date) = todo.creation_date {
+            dates.push(format!("created {}", date.format(DATE_FORMAT)));
+        }
+        if let Some(date) = todo.due_date() {
+            dates.push(format!("due {} ({})", relative(date, today), date.format(DATE_FORMAT)));
+        }
+        if let Some(date) = todo.completion_date {
+            dates.push(format!("done {}", date.format(DATE_FORMAT)));
+        }
+        if !dates.is_empty() {
+            lines.push(dates.join(", "));
+        }
+        if let Some(parent) = todo.parent().and_then(|parent| collection.iter().find(|task| task.id == parent)) {
+            lines.push(format!("Subtask of \"{}\"", parent.description));
+        }
+        if let Some((done, total)) = subtask_progress(collection).get(todo.id.as_str()) {
+            lines.push(format!("{} of {} subtasks done", done, total));
+        }
+        lines.push(format!("id {}", todo.id));
+        execute!(
+            stdout,
+            cursor::MoveToNextLine(1),
+            SetForegroundColor(Color::DarkGrey),
+            Print("-- Details (v: close, n: edit notes, N: edit in $EDITOR) --"),
+            SetForegroundColor(Color::White),
+        )?;
+        for line in lines {
+            execute!(stdout, cursor::MoveToNextLine(1), Print(line))?;
+        }
+        execute!(stdout, cursor::MoveToNextLine(1), SetForegroundColor(Color::DarkGrey), Print("Notes:"), ResetColor)?;
+        if todo.notes.is_empty() {
+            execute!(stdout, cursor::MoveToNextLine(1), Print("  (none)"))?;
+        }
+        for line in todo.notes.lines() {
+            execute!(stdout, cursor::MoveToNextLine(1), Print(format!("  {}", line)))?;
+        }
+        Ok(())
+    }
 
-        stdout.flush()?;
+    fn render_list_picker(&self, stdout: &mut io::Stdout, picker: &ListPicker) -> Result<()> {
+        execute!(
+            stdout,
+            cursor::MoveTo(0, HEADER_ROW),
+            Print("Lists (enter: open, n: new, r: rename, D: delete, esc: close)"),
+            cursor::MoveToNextLine(1),
+        )?;
+        for (index, name) in picker.names.iter().enumerate() {
+            let marker = if *name == self.list_name { "*" } else { " " };
+            execute!(
+                stdout,
+                SetForegroundColor(Color::White),
+                SetBackgroundColor(if picker.cursor_row == index { Color::Blue } else { Color::Black }),
+                Print(format!("{} {}", marker, name)),
+                ResetColor,
+                cursor::MoveToNextLine(1),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn render_tag_picker(&self, stdout: &mut io::Stdout, picker: &TagPicker) -> Result<()> {
+        execute!(
+            stdout,
+            cursor::MoveTo(0, HEADER_ROW),
+            Print("Projects and contexts (enter: show its tasks, esc: close)"),
+            cursor::MoveToNextLine(1),
+        )?;
+        let width = picker.tags.iter().map(|count| count.tag.len()).max().unwrap_or(0).max("all tasks".len());
+        let rows = std::iter::once((None, String::from("all tasks"), String::new()))
+            .chain(picker.tags.iter().map(|count| (Some(&count.tag), count.tag.clone(), format!("{} open, {} done", count.open, count.done))));
+        for (index, (tag, label, counts)) in rows.enumerate() {
+            let marker = if tag == self.tag_filter.as_ref() { "*" } else { " " };
+            let color = match tag {
+                Some(tag) if tag.starts_with('@') => Color::Cyan,
+                Some(_) => Color::Green,
+                None => Color::White,
+            };
+            execute!(
+                stdout,
+                SetForegroundColor(color),
+                SetBackgroundColor(if picker.cursor_row == index { Color::Blue } else { Color::Black }),
+                Print(format!("{} {:<width$}  ", marker, label)),
+                SetForegroundColor(Color::White),
+                Print(counts),
+                ResetColor,
+                cursor::MoveToNextLine(1),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn render_history(&self, stdout: &mut io::Stdout, history: &HistoryView) -> Result<()> {
+        execute!(
+            stdout,
+            cursor::MoveTo(0, HEADER_ROW),
+            Print("History (enter: restore, esc: close)"),
+            cursor::MoveToNextLine(1),
+        )?;
+        if history.versions.is_empty() {
+            execute!(stdout, Print("No versions yet"), cursor::MoveToNextLine(1))?;
+        }
+        for (index, (_, label)) in history.versions.iter().enumerate() {
+            execute!(
+                stdout,
+                SetForegroundColor(Color::White),
+                SetBackgroundColor(if history.cursor_row == index { Color::Blue } else { Color::Black }),
+                Print(label),
+                ResetColor,
+                cursor::MoveToNextLine(1),
+            )?;
+        }
         Ok(())
     }
 
@@ -143,139 +444,459 @@ impl TodoUI {
     //     Ok(())
     // }
 
-    pub fn read_event(&mut self, todos: &mut Todos) -> Result<Option<UiEvent>> {
-        if let Ok(event) = event::read() {
-                // Handle key events
-                match event {
-                    Event::Key(event) => match event.code {
-                        // Quit
-                        KeyCode::Char('q') => return Ok(Some(UiEvent::Quit)),
-                        // Add todo
-                        KeyCode::Char('a') => {
-                            execute!(io::stdout(), cursor::Show, cursor::EnableBlinking).unwrap();
-                            terminal::disable_raw_mode().unwrap();
-                            execute!(io::stdout(), cursor::MoveToNextLine(1), Print(">> ")).unwrap();
-                            return Ok(Some(UiEvent::AddTodo))
-                        }
-                        // Remove todo
-                        KeyCode::Char('d') => {
-                           todos.remove_todo(self.cursor_row, match self.active_screen {
-                               TodoTab::TodoList => None,
-                               TodoTab::DoneList => Some(true),
-                               TodoTab::UndoneList => Some(false),
-                           });
-                           return Ok(Some(UiEvent::RefreshUI));
-                        }
-                        // Navigate tabs
-                        KeyCode::Char('l') | KeyCode::Right => {
-                            self.cursor_row = 0;
-                            let next_screen = match self.active_screen {
-                                TodoTab::TodoList => TodoTab::DoneList,
-                                TodoTab::DoneList => TodoTab::UndoneList,
-                                TodoTab::UndoneList => TodoTab::TodoList,
-                            };
-                            return Ok(Some(UiEvent::ChangeTab(next_screen)));
-                        }
-                        KeyCode::Char('h') | KeyCode::Left => {
-                            self.cursor_row = 0;
-                            let next_screen = match self.active_screen {
-                                TodoTab::TodoList => TodoTab::UndoneList,
-                                TodoTab::DoneList => TodoTab::TodoList,
-                                TodoTab::UndoneList => TodoTab::DoneList,
-                            };
-                            return Ok(Some(UiEvent::ChangeTab(next_screen)));
-                        }
-                        // Select todo (up/down)
-                        KeyCode::Char('j') | KeyCode::Down => {
-                            match self.active_screen {
-                                TodoTab::TodoList => {
-                                    if self.cursor_row < todos.get_number_of_tasks(None) - 1 {
-                                        self.cursor_row += 1;
-                                    }
-                                }
-                                TodoTab::DoneList => {
-                                    if self.cursor_row < todos.get_number_of_tasks(Some(true)) - 1 {
-                                        self.cursor_row += 1;
-                                    }
-                                }
-                                TodoTab::UndoneList => {
-                                    if self.cursor_row < todos.get_number_of_tasks(Some(false)) - 1 {
-                                        self.cursor_row += 1;
-                                    }
-                                }
-                            }
-                            return Ok(Some(UiEvent::RefreshUI));
-                        }
-                        KeyCode::Char('k') | KeyCode::Up => {
-                            if self.cursor_row > 0 {
-                                self.cursor_row -= 1;
-                            }
-                            return Ok(Some(UiEvent::RefreshUI));
+    pub fn read_event(&mut self, todos: &Todos) -> Result<Option<UiEvent>> {
+        // Don't block forever, so the app can react to the file changing
+        if !event::poll(POLL_INTERVAL)? {
+            return Ok(None);
+        }
+        // Handle key events
+        if let Ok(Event::Key(event)) = event::read() {
+            self.message = None;
+            if self.list_picker.is_some() {
+                return self.read_list_picker_event(event.code);
+            }
+            if self.tag_picker.is_some() {
+                return Ok(self.read_tag_picker_event(event.code));
+            }
+            if self.history.is_some() {
+                return Ok(self.read_history_event(event.code));
+            }
+            // Archived and deleted tasks can only be restored, or purged
+            // from the trash
+            match (&self.active_screen, event.code) {
+                (TodoTab::ArchiveList, KeyCode::Char('r')) => return Ok(self.selected_id(todos).map(UiEvent::Unarchive)),
+                (TodoTab::TrashList, KeyCode::Char('r')) => return Ok(self.selected_id(todos).map(UiEvent::RestoreDeleted)),
+                (TodoTab::TrashList, KeyCode::Char('d')) => {
+                    if let Some(id) = self.selected_id(todos) {
+                        let description = &self.visible_tasks(todos)[self.cursor_row].description;
+                        self.open_prompt(Some(&format!("Delete \"{}\" for good? (y/n)", description)))?;
+                        return Ok(Some(UiEvent::PurgeTodo(id)));
+                    }
+                    return Ok(None);
+                }
+                (TodoTab::ArchiveList | TodoTab::TrashList, KeyCode::Char('e') | KeyCode::Char('d') | KeyCode::Char('x') | KeyCode::Char('+') | KeyCode::Char('-') | KeyCode::Char('o') | KeyCode::Char('n') | KeyCode::Char('N')) => return Ok(None),
+                _ => {}
+            }
+            match event.code {
+                // Quit
+                KeyCode::Char('q') => return Ok(Some(UiEvent::Quit)),
+                // Add todo
+                KeyCode::Char('a') => {
+                    self.open_prompt(None)?;
+                    return Ok(Some(UiEvent::AddTodo))
+                }
+                // Add a subtask under the selected todo
+                KeyCode::Char('o') => {
+                    if let Some(id) = self.selected_id(todos) {
+                        let description = &self.visible_tasks(todos)[self.cursor_row].description;
+                        self.open_prompt(Some(&format!("Subtask of \"{}\":", description)))?;
+                        return Ok(Some(UiEvent::AddSubtask(id)));
+                    }
+                }
+                // Hide or show the subtasks of the selected todo
+                KeyCode::Char('c') => {
+                    if let Some(id) = self.selected_id(todos) {
+                        if !self.collapsed.remove(&id) && subtask_progress(self.collection(todos)).contains_key(id.as_str()) {
+                            self.collapsed.insert(id);
                         }
-                        // Mark as done/undone
-                        KeyCode::Char('x') => {
-                            match todos.get_task(self.cursor_row, match self.active_screen {
-                                    TodoTab::TodoList => None,
-                                    TodoTab::DoneList => Some(true),
-                                    TodoTab::UndoneList => Some(false),
-                                }
-                            ){
-
-                                Some(todo) => {
-                                    match todo.is_done() {
-                                        true => todo.mark_as_undone(),
-                                        false => todo.mark_as_done(),
-                                    };
-                                },
-                                None => {},
-                            }
-                            return Ok(Some(UiEvent::RefreshUI));
                     }
-                    _ => {}
-                },
+                    return Ok(Some(UiEvent::RefreshUI));
+                }
+                // Show or hide the details pane
+                KeyCode::Char('v') => {
+                    self.show_details = !self.show_details;
+                    return Ok(Some(UiEvent::RefreshUI));
+                }
+                // Edit the notes of the selected todo here or in $EDITOR
+                KeyCode::Char('n') => {
+                    if let Some(id) = self.selected_id(todos) {
+                        return Ok(Some(UiEvent::EditNotes(id)));
+                    }
+                }
+                KeyCode::Char('N') => {
+                    if let Some(id) = self.selected_id(todos) {
+                        return Ok(Some(UiEvent::EditNotesInEditor(id)));
+                    }
+                }
+                // Edit the selected todo's description
+                KeyCode::Char('e') => {
+                    if let Some(id) = self.selected_id(todos) {
+                        let description = &self.visible_tasks(todos)[self.cursor_row].description;
+                        self.open_prompt(Some(&format!("editing: {}", description)))?;
+                        return Ok(Some(UiEvent::EditTodo(id)));
+                    }
+                }
+                // Remove todo
+                KeyCode::Char('d') => {
+                    if let Some(id) = self.selected_id(todos) {
+                        return Ok(Some(UiEvent::RemoveTodo(id)));
+                    }
+                }
+                // Pick another list
+                KeyCode::Char('L') => return Ok(Some(UiEvent::OpenListPicker)),
+                // Pick a project or context to show the tasks of
+                KeyCode::Char('p') => {
+                    let tags = todos.tag_counts();
+                    let cursor_row = self.tag_filter.as_ref().and_then(|filter| tags.iter().position(|count| count.tag == *filter)).map_or(0, |index| index + 1);
+                    self.tag_picker = Some(TagPicker { tags, cursor_row });
+                    return Ok(Some(UiEvent::RefreshUI));
+                }
+                // Move done tasks to the archive
+                KeyCode::Char('A') => return Ok(Some(UiEvent::Archive)),
+                // Browse and restore past versions
+                KeyCode::Char('H') => return Ok(Some(UiEvent::OpenHistory)),
+                // Add tasks from a checklist, a calendar or another todo app
+                KeyCode::Char('i') => {
+                    self.open_prompt(Some("Import tasks from (.md, .ics, Taskwarrior .json, Todoist .csv/.json):"))?;
+                    return Ok(Some(UiEvent::Import));
+                }
+                // Write the tasks shown to a file or a report
+                KeyCode::Char('w') => {
+                    self.open_prompt(Some(&format!("Export the {} tab to (.md, .ics, .html, .csv):", self.tab_name())))?;
+                    return Ok(Some(UiEvent::Export));
+                }
+                // Only show tasks containing some text
+                KeyCode::Char('/') => {
+                    self.open_prompt(Some("Search (empty to show every task):"))?;
+                    return Ok(Some(UiEvent::Search));
+                }
+                // Encrypt, re-key or decrypt the list
+                KeyCode::Char('E') => return Ok(Some(UiEvent::ChangeEncryption)),
+                // Roll back to the latest backup
+                KeyCode::Char('R') => return Ok(Some(UiEvent::RestoreBackup)),
+                // Navigate tabs
+                KeyCode::Char('l') | KeyCode::Right => {
+                    self.cursor_row = 0;
+                    let next_screen = match self.active_screen {
+                        TodoTab::TodoList => TodoTab::DoneList,
+                        TodoTab::DoneList => TodoTab::UndoneList,
+                        TodoTab::UndoneList => TodoTab::ArchiveList,
+                        TodoTab::ArchiveList => TodoTab::TrashList,
+                        TodoTab::TrashList => TodoTab::TodoList,
+                    };
+                    return Ok(Some(UiEvent::ChangeTab(next_screen)));
+                }
+                KeyCode::Char('h') | KeyCode::Left => {
+                    self.cursor_row = 0;
+                    let next_screen = match self.active_screen {
+                        TodoTab::TodoList => TodoTab::TrashList,
+                        TodoTab::DoneList => TodoTab::TodoList,
+                        TodoTab::UndoneList => TodoTab::DoneList,
+                        TodoTab::ArchiveList => TodoTab::UndoneList,
+                        TodoTab::TrashList => TodoTab::ArchiveList,
+                    };
+                    return Ok(Some(UiEvent::ChangeTab(next_screen)));
+                }
+                // Select todo (up/down)
+                KeyCode::Char('j') | KeyCode::Down => {
+                    if self.cursor_row + 1 < self.visible_tasks(todos).len() {
+                        self.cursor_row += 1;
+                    }
+                    return Ok(Some(UiEvent::RefreshUI));
+                }
+                KeyCode::Char('k') | KeyCode::Up => {
+                    if self.cursor_row > 0 {
+                        self.cursor_row -= 1;
+                    }
+                    return Ok(Some(UiEvent::RefreshUI));
+                }
+                // Mark as done/undone
+                KeyCode::Char('x') => {
+                    if let Some(id) = self.selected_id(todos) {
+                        return Ok(Some(UiEvent::ToggleTodo(id)));
+                    }
+                }
+                // Raise or lower the priority
+                KeyCode::Char('+') => {
+                    if let Some(id) = self.selected_id(todos) {
+                        return Ok(Some(UiEvent::RaisePriority(id)));
+                    }
+                }
+                KeyCode::Char('-') => {
+                    if let Some(id) = self.selected_id(todos) {
+                        return Ok(Some(UiEvent::LowerPriority(id)));
+                    }
+                }
+                // Sort by priority, or back to the order of the file
+                KeyCode::Char('s') => {
+                    self.sort_by_priority = !self.sort_by_priority;
+                    self.cursor_row = 0;
+                    return Ok(Some(UiEvent::RefreshUI));
+                }
                 _ => {}
             }
         }
         Ok(None)
     }
 
+    fn read_list_picker_event(&mut self, code: KeyCode) -> Result<Option<UiEvent>> {
+        let Some(picker) = self.list_picker.as_mut() else {
+            return Ok(None);
+        };
+        let selected = picker.names.get(picker.cursor_row).cloned();
+        match code {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('L') => {
+                self.list_picker = None;
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                picker.cursor_row = (picker.cursor_row + 1).min(picker.names.len().saturating_sub(1));
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                picker.cursor_row = picker.cursor_row.saturating_sub(1);
+            }
+            KeyCode::Enter => {
+                if let Some(name) = selected {
+                    self.list_picker = None;
+                    self.cursor_row = 0;
+                    return Ok(Some(UiEvent::SwitchList(name)));
+                }
+            }
+            KeyCode::Char('n') => {
+                self.list_picker = None;
+                self.open_prompt(Some("New list name:"))?;
+                return Ok(Some(UiEvent::CreateList));
+            }
+            KeyCode::Char('r') => {
+                if let Some(name) = selected {
+                    self.list_picker = None;
+                    self.open_prompt(Some(&format!("Rename \"{}\" to:", name)))?;
+                    return Ok(Some(UiEvent::RenameList(name)));
+                }
+            }
+            KeyCode::Char('D') => {
+                if let Some(name) = selected {
+                    self.list_picker = None;
+                    self.open_prompt(Some(&format!("Delete list \"{}\" and its history? (y/n)", name)))?;
+                    return Ok(Some(UiEvent::DeleteList(name)));
+                }
+            }
+            _ => {}
+        }
+        Ok(Some(UiEvent::RefreshUI))
+    }
+
+    fn read_tag_picker_event(&mut self, code: KeyCode) -> Option<UiEvent> {
+        let picker = self.tag_picker.as_mut()?;
+        match code {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('p') => {
+                self.tag_picker = None;
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                picker.cursor_row = (picker.cursor_row + 1).min(picker.tags.len());
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                picker.cursor_row = picker.cursor_row.saturating_sub(1);
+            }
+            KeyCode::Enter => {
+                self.tag_filter = picker.cursor_row.checked_sub(1).and_then(|index| picker.tags.get(index)).map(|count| count.tag.clone());
+                self.tag_picker = None;
+                self.cursor_row = 0;
+            }
+            _ => {}
+        }
+        Some(UiEvent::RefreshUI)
+    }
+
+    fn read_history_event(&mut self, code: KeyCode) -> Option<UiEvent> {
+        let history = self.history.as_mut()?;
+        match code {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('H') => {
+                self.history = None;
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                history.cursor_row = (history.cursor_row + 1).min(history.versions.len().saturating_sub(1));
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                history.cursor_row = history.cursor_row.saturating_sub(1);
+            }
+            KeyCode::Enter => {
+                if let Some((id, _)) = history.versions.get(history.cursor_row) {
+                    let id = id.clone();
+                    self.history = None;
+                    return Some(UiEvent::RestoreVersion(id));
+                }
+            }
+            _ => {}
+        }
+        Some(UiEvent::RefreshUI)
+    }
+
+    pub fn open_history(&mut self, versions: Vec<(String, String)>) {
+        self.history = Some(HistoryView { versions, cursor_row: 0 });
+    }
+
+    pub fn open_list_picker(&mut self, names: Vec<String>) {
+        let cursor_row = names.iter().position(|name| *name == self.list_name).unwrap_or(0);
+        self.list_picker = Some(ListPicker { names, cursor_row });
+    }
+
+    pub fn set_list_name(&mut self, name: String) {
+        self.list_name = name;
+    }
+
+    fn selected_id(&self, todos: &Todos) -> Option<String> {
+        self.visible_tasks(todos).get(self.cursor_row).map(|todo| todo.id.clone())
+    }
+
+    fn tab_name(&self) -> &'static str {
+        match self.active_screen {
+            TodoTab::TodoList => "All",
+            TodoTab::DoneList => "Done",
+            TodoTab::UndoneList => "Undone",
+            TodoTab::ArchiveList => "Archive",
+            TodoTab::TrashList => "Trash",
+        }
+    }
+
+    pub fn set_search(&mut self, search: &str) {
+        self.search = Some(search.trim().to_string()).filter(|search| !search.is_empty());
+        self.cursor_row = 0;
+    }
+
+    // Names what `visible_tasks` returns, e.g. `work: Undone +app matching "bug"`
+    pub fn view_title(&self) -> String {
+        let mut title = format!("{}: {}", self.list_name, self.tab_name());
+        if let Some(tag) = &self.tag_filter {
+            title.push_str(&format!(" {}", tag));
+        }
+        if let Some(search) = &self.search {
+            title.push_str(&format!(" matching \"{}\"", search));
+        }
+        title
+    }
+
+    // The tasks of the active tab, in the order they are shown
+    pub fn visible_tasks<'a>(&self, todos: &'a Todos) -> Vec<&'a Todo> {
+        let mut tasks = self.tab_tasks(todos);
+        if let Some(tag) = &self.tag_filter {
+            tasks.retain(|todo| todo.has_tag(tag));
+        }
+        if let Some(search) = &self.search {
+            tasks.retain(|todo| todo.matches(search));
+        }
+        // A to Z, then tasks without a priority. The sort is stable, tasks
+        // with the same priority keep their order.
+        if self.sort_by_priority {
+            tasks.sort_by_key(|todo| todo.effective_priority().unwrap_or(char::MAX));
+        }
+        tree_order(&tasks, &self.collapsed).into_iter().map(|(todo, _)| todo).collect()
+    }
+
+    // Every task of the file the active tab shows, subtasks included
+    fn collection<'a>(&self, todos: &'a Todos) -> &'a [Todo] {
+        match self.active_screen {
+            TodoTab::ArchiveList => &todos.archived,
+            TodoTab::TrashList => &todos.trashed,
+            _ => &todos.todos,
+        }
+    }
+
+    pub fn expand(&mut self, id: &str) {
+        self.collapsed.remove(id);
+    }
+
+    fn tab_tasks<'a>(&self, todos: &'a Todos) -> Vec<&'a Todo> {
+        match self.active_screen {
+            TodoTab::TodoList => todos.get_tasks(None),
+            TodoTab::DoneList => todos.get_tasks(Some(true)),
+            TodoTab::UndoneList => todos.get_tasks(Some(false)),
+            TodoTab::ArchiveList => todos.archived.iter().collect(),
+            TodoTab::TrashList => todos.trashed.iter().collect(),
+        }
+    }
+
+    pub fn set_message(&mut self, message: Message) {
+        self.message = Some(message);
+    }
 
     pub fn change_screen(&mut self, screen: TodoTab) {
         self.active_screen = screen;
     }
 
     pub fn refresh_screen(&mut self, todos: &Todos) {
-        let cursor_row = match self.active_screen {
-            TodoTab::TodoList => todos.get_number_of_tasks(None).saturating_sub(1),
-            TodoTab::DoneList => todos.get_number_of_tasks(Some(true)).saturating_sub(1),
-            TodoTab::UndoneList => todos.get_number_of_tasks(Some(false)).saturating_sub(1),
-        };
+        let cursor_row = self.visible_tasks(todos).len().saturating_sub(1);
         self.cursor_row = self.cursor_row.min(cursor_row);
     }
 
-    pub fn add_todo(&self) {
-        execute!(io::stdout(), cursor::Hide, cursor::DisableBlinking).unwrap();
-        terminal::enable_raw_mode().unwrap();
+    // Leaves raw mode so a line can be typed in with `read_line`
+    pub fn open_prompt(&self, label: Option<&str>) -> Result<()> {
+        let mut stdout = io::stdout();
+        execute!(stdout, cursor::Show, cursor::EnableBlinking)?;
+        terminal::disable_raw_mode()?;
+        if let Some(label) = label {
+            execute!(stdout, cursor::MoveToNextLine(1), Print(label))?;
+        }
+        execute!(stdout, cursor::MoveToNextLine(1), Print(">> "))?;
+        Ok(())
+    }
+
+    pub fn close_prompt(&self) {
+        // Best effort: a failure here shows up on the next render anyway
+        let _ = execute!(io::stdout(), cursor::Hide, cursor::DisableBlinking);
+        let _ = terminal::enable_raw_mode();
     }
 
 
-    fn render_todos(&self, stdout: &mut io::Stdout, todos: &Todos, done: Option<bool>) -> Result<()> {
-        let format_string = |index: usize, description: &str, status: &str| -> String {
-            let truncated_desc = if description.len() > (DESCRIPTION_WIDTH - 3) {
-                format!("{}...", &description[..DESCRIPTION_WIDTH - 3])
+    // `todos` are in tree order, `collection` is every task of their file
+    fn render_todos(&self, stdout: &mut io::Stdout, todos: &[&Todo], collection: &[Todo]) -> Result<()> {
+        // Subtasks are indented under their parent, which shows whether
+        // they are collapsed
+        let format_string = |todo: &Todo, depth: usize, has_subtasks: bool| -> String {
+            let mut shown = todo.clone();
+            shown.set_tag(PARENT_TAG, None);
+            let marker = match (has_subtasks, self.collapsed.contains(&todo.id)) {
+                (false, _) => "",
+                (true, true) => "▸ ",
+                (true, false) => "▾ ",
+            };
+            let description = match todo.priority {
+                Some(priority) => format!("{}{}({}) {}", "  ".repeat(depth), marker, priority, shown.description),
+                None => format!("{}{}{}", "  ".repeat(depth), marker, shown.description),
+            };
+            let truncated_desc = if description.chars().count() > (DESCRIPTION_WIDTH - 3) {
+                format!("{}...", description.chars().take(DESCRIPTION_WIDTH - 3).collect::<String>())
             } else {
-                description.to_string()
+                description
             };
-            format!("{:>INDEX_WIDTH$} | {:>width$} | {:^STATUS_WIDTH$}", index+1, truncated_desc, status, width=DESCRIPTION_WIDTH)
+            format!("{:<width$}", truncated_desc, width=DESCRIPTION_WIDTH)
         };
 
-        let todos = todos.get_tasks(done);
+        let today = Local::now().date_naive();
+        let mut depths = HashMap::new();
+        let progress = subtask_progress(collection);
         for (index, todo) in todos.iter().enumerate() {
+            let depth = todo.parent().and_then(|parent| depths.get(parent)).map_or(0, |depth| depth + 1);
+            depths.insert(todo.id.as_str(), depth);
+            let subtasks = progress.get(todo.id.as_str()).copied();
+            let mut status = match (&self.active_screen, todo.deleted_at(), subtasks) {
+                (TodoTab::TrashList, Some(deleted), _) => deleted.with_timezone(&Local).format(DATE_FORMAT).to_string(),
+                (_, _, Some((done, total))) => format!("{} {}/{}", if todo.done { "[x]" } else { "[ ]" }, done, total),
+                _ => String::from(if todo.done { "[x]" } else { "[ ]" }),
+            };
+            // Tasks with notes
+            if !todo.notes.is_empty() {
+                status.push_str(" ✎");
+            }
+            // Open tasks that are overdue are red, the ones due today yellow
+            let due = todo.due_date();
+            let due_color = match due {
+                Some(due) if !todo.done && due < today => Color::Red,
+                Some(due) if !todo.done && due == today => Color::Yellow,
+                _ => Color::White,
+            };
             execute!(
                 stdout,
                 SetForegroundColor(Color::White),
                 SetBackgroundColor(if self.cursor_row == index { Color::Blue } else { Color::Black }),
-                Print(format_string(index, &todo.description, if todo.done { "[x]" } else { "[ ]" })),
+                Print(format!("{:>INDEX_WIDTH$} | ", index+1)),
+                SetForegroundColor(priority_color(todo.effective_priority())),
+                Print(format_string(todo, depth, subtasks.is_some())),
+                SetForegroundColor(Color::White),
+                Print(" | "),
+                SetForegroundColor(due_color),
+                Print(format!("{:^DUE_WIDTH$}", due.map(|due| relative(due, today)).unwrap_or_default())),
+                SetForegroundColor(Color::White),
+                Print(format!(" | {:^STATUS_WIDTH$}", status)),
                 // Print(format!("{:>3}. {:>30} [{:^10}]", index + 1, todo.description, if todo.done { "x" } else { " " })),
                 cursor::MoveToNextLine(1),
             )?;
@@ -312,4 +933,76 @@ impl TodoUI {
 
         Ok(line)
     }
+
+    // Reads several lines of text in raw mode, starting from `text`. Enter
+    // starts a new line, Ctrl-D saves and Esc cancels with `None`.
+    pub fn read_text(&self, label: &str, text: &str) -> io::Result<Option<String>> {
+        let mut stdout = io::stdout();
+        let mut text = text.to_string();
+        execute!(stdout, cursor::SavePosition, cursor::Show)?;
+        let edited = loop {
+            execute!(stdout, cursor::RestorePosition, Clear(ClearType::FromCursorDown), ResetColor, cursor::MoveToNextLine(1), Print(label))?;
+            for line in text.split('\n') {
+                execute!(stdout, cursor::MoveToNextLine(1), Print(line))?;
+            }
+            if let Event::Key(KeyEvent { code, modifiers, .. }) = event::read()? {
+                match code {
+                    KeyCode::Char('d') if modifiers.contains(KeyModifiers::CONTROL) => break Some(text),
+                    KeyCode::Esc => break None,
+                    KeyCode::Enter => text.push('\n'),
+                    KeyCode::Tab => text.push('\t'),
+                    KeyCode::Backspace => {
+                        text.pop();
+                    }
+                    KeyCode::Char(c) => text.push(c),
+                    _ => {}
+                }
+            }
+        };
+        execute!(stdout, cursor::Hide)?;
+        Ok(edited)
+    }
+
+    // Reads a passphrase in raw mode, so it isn't echoed. Esc cancels and
+    // returns an empty string.
+    pub fn read_secret(&self, label: &str) -> io::Result<Zeroizing<String>> {
+        let mut stdout = io::stdout();
+        execute!(stdout, cursor::MoveToNextLine(1), ResetColor, Print(label), Print(" "), cursor::Show)?;
+        let mut secret = Zeroizing::new(String::new());
+        loop {
+            if let Event::Key(KeyEvent { code, .. }) = event::read()? {
+                match code {
+                    KeyCode::Enter => break,
+                    KeyCode::Esc => {
+                        secret.clear();
+                        break;
+                    }
+                    KeyCode::Backspace if secret.pop().is_some() => {
+                        execute!(stdout, cursor::MoveLeft(1), Print(" "), cursor::MoveLeft(1))?;
+                    }
+                    KeyCode::Char(c) => {
+                        secret.push(c);
+                        execute!(stdout, Print("*"))?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        execute!(stdout, cursor::Hide)?;
+        Ok(secret)
+    }
+}
+
+// A stands out the most, priorities past F are all grey
+fn priority_color(priority: Option<char>) -> Color {
+    match priority {
+        Some('A') => Color::Red,
+        Some('B') => Color::Yellow,
+        Some('C') => Color::Green,
+        Some('D') => Color::Cyan,
+        Some('E') => Color::Magenta,
+        Some('F') => Color::DarkYellow,
+        Some(p @ 'G'..='Z') => Color::AnsiValue(LOW_PRIORITY_COLORS[(p as u8 - b'G') as usize]),
+        _ => Color::White,
+    }
 }
@@ -0,0 +1,62 @@
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+
+use crate::error::TodoError;
+
+// Watches a list file for changes made by other programs. The parent
+// directory is watched rather than the file itself, since atomic saves
+// replace the file with a new inode.
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    file_name: OsString,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Result<Self, TodoError> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let file_name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            file_name,
+        })
+    }
+
+    // Drains pending events and tells whether any of them touched the file
+    pub fn changed(&self) -> bool {
+        let mut changed = false;
+        while let Ok(event) = self.events.try_recv() {
+            if let Ok(event) = event {
+                changed |= event.paths.iter().any(|path| self.is_watched(path));
+            }
+        }
+        changed
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return false;
+        };
+        if name == self.file_name {
+            return true;
+        }
+        // SQLite in WAL mode writes to `<file>-wal` until a checkpoint, and
+        // most changes only go to the journal
+        ["-wal", ".journal"].iter().any(|suffix| {
+            let mut sibling = self.file_name.clone();
+            sibling.push(suffix);
+            name == sibling
+        })
+    }
+}